@@ -0,0 +1,39 @@
+use anchor_lang::prelude::*;
+
+use crate::liquidity_vault::CustomError;
+
+/// Highest performance fee, as bps of harvested trading fees
+pub const MAX_PERFORMANCE_FEE_BPS: u16 = 3_000;
+/// Highest management fee, as bps of the vault per year
+pub const MAX_MANAGEMENT_FEE_BPS: u16 = 500;
+/// Delay between proposing a fee change and being able to apply it
+pub const FEE_TIMELOCK_SECONDS: i64 = 2 * 24 * 60 * 60;
+
+/// Vault fees, both paid by minting shares to `treasury`.
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace,
+)]
+pub struct FeeConfig {
+    /// Share token account receiving the fee shares
+    pub treasury: Pubkey,
+    /// Charged on the trading fees harvested by rebalance and compound
+    pub performance_fee_bps: u16,
+    /// Charged on the whole vault, per year
+    pub management_fee_bps: u16,
+}
+
+impl FeeConfig {
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.performance_fee_bps <= MAX_PERFORMANCE_FEE_BPS
+                && self.management_fee_bps <= MAX_MANAGEMENT_FEE_BPS,
+            CustomError::FeeTooHigh
+        );
+        require!(
+            self.treasury != Pubkey::default()
+                || (self.performance_fee_bps == 0 && self.management_fee_bps == 0),
+            CustomError::MissingTreasury
+        );
+        Ok(())
+    }
+}
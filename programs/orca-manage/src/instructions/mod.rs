@@ -1,4 +1,13 @@
-pub mod proxy_open_position;
 pub mod proxy_close_position;
 pub mod proxy_collect_fees;
 pub mod proxy_collect_reward;
+pub mod proxy_decrease_liquidity;
+pub mod proxy_increase_liquidity;
+pub mod proxy_open_position;
+
+pub use proxy_close_position::*;
+pub use proxy_collect_fees::*;
+pub use proxy_collect_reward::*;
+pub use proxy_decrease_liquidity::*;
+pub use proxy_increase_liquidity::*;
+pub use proxy_open_position::*;
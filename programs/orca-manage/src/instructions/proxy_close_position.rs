@@ -1,14 +1,20 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, Mint, TokenAccount};
-use whirlpool_cpi::{self, state::*, program::Whirlpool as WhirlpoolProgram};
+use anchor_spl::token::{self, Token};
+use anchor_spl::token_2022::Token2022;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+use whirlpool_cpi::{state::*, program::Whirlpool as WhirlpoolProgram};
 
-use crate::Rebalance;
+use crate::liquidity_vault::CustomError;
+use crate::{position, Vault};
 
 #[derive(Accounts)]
 pub struct ProxyClosePosition<'info> {
   pub whirlpool_program: Program<'info, WhirlpoolProgram>,
 
-  pub position_authority: Signer<'info>,
+  #[account(mut, has_one = admin,
+      constraint = vault.position_slot(&position.key()).is_some() @ CustomError::InvalidPositionAccounts)]
+  pub vault: Account<'info, Vault>,
+  pub admin: Signer<'info>,
 
   /// CHECK: safe (the account to receive the remaining balance of the closed account)
   #[account(mut)]
@@ -18,36 +24,46 @@ pub struct ProxyClosePosition<'info> {
   pub position: Account<'info, Position>,
 
   #[account(mut, address = position.position_mint)]
-  pub position_mint: Account<'info, Mint>,
+  pub position_mint: InterfaceAccount<'info, Mint>,
 
   #[account(mut,
       constraint = position_token_account.amount == 1,
-      constraint = position_token_account.mint == position.position_mint)]
-  pub position_token_account: Box<Account<'info, TokenAccount>>,
+      constraint = position_token_account.mint == position.position_mint,
+      constraint = position_token_account.owner == vault.key())]
+  pub position_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
   #[account(address = token::ID)]
   pub token_program: Program<'info, Token>,
+  /// Token-2022 positions only
+  pub token_2022_program: Option<Program<'info, Token2022>>,
 }
 
 pub fn close_position_handler(
   ctx: Context<ProxyClosePosition>,
 ) -> Result<()> {
-  let cpi_program = ctx.accounts.whirlpool_program.to_account_info();
-
-  let cpi_accounts = whirlpool_cpi::cpi::accounts::ClosePosition {
-    position_authority: ctx.accounts.position_authority.to_account_info(),
-    receiver: ctx.accounts.receiver.to_account_info(),
-    position: ctx.accounts.position.to_account_info(),
-    position_mint: ctx.accounts.position_mint.to_account_info(),
-    position_token_account: ctx.accounts.position_token_account.to_account_info(),
-    token_program: ctx.accounts.token_program.to_account_info(),
-  };
-
-  let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-
-  // execute CPI
-  msg!("CPI: whirlpool close_position instruction");
-  whirlpool_cpi::cpi::close_position(cpi_ctx)?;
+  position::close_position(
+    &ctx.accounts.vault,
+    position::ClosePositionAccounts {
+      whirlpool_program: ctx.accounts.whirlpool_program.to_account_info(),
+      receiver: ctx.accounts.receiver.to_account_info(),
+      position: ctx.accounts.position.to_account_info(),
+      position_mint: ctx.accounts.position_mint.to_account_info(),
+      position_token_account: ctx.accounts.position_token_account.to_account_info(),
+      token_program: ctx.accounts.token_program.to_account_info(),
+      token_2022_program: ctx
+        .accounts
+        .token_2022_program
+        .as_ref()
+        .map(|program| program.to_account_info()),
+    },
+  )?;
+
+  // free the position's slot
+  let vault = &mut ctx.accounts.vault;
+  let slot = vault
+    .position_slot(&ctx.accounts.position.key())
+    .ok_or(CustomError::InvalidPositionAccounts)?;
+  vault.positions[slot].close();
 
   Ok(())
 }
@@ -1,56 +1,76 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount};
+use anchor_spl::memo::Memo;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use whirlpool_cpi::{self, program::Whirlpool as WhirlpoolProgram, state::*};
 
-use crate::Rebalance;
+use crate::liquidity_vault::CustomError;
+use crate::Vault;
 
 #[derive(Accounts)]
 #[instruction(reward_index: u8)]
 pub struct ProxyCollectReward<'info> {
     pub whirlpool_program: Program<'info, WhirlpoolProgram>,
 
+    // checked ahead of the constraints below that index the reward slots
+    #[account(
+      constraint = (reward_index as usize) < whirlpool.reward_infos.len() @ CustomError::InvalidRewardAccounts
+  )]
     pub whirlpool: Box<Account<'info, Whirlpool>>,
 
-    pub position_authority: Signer<'info>,
+    #[account(has_one = whirlpool, has_one = admin,
+      constraint = vault.position_slot(&position.key()).is_some() @ CustomError::InvalidPositionAccounts)]
+    pub vault: Account<'info, Vault>,
+    pub admin: Signer<'info>,
 
     #[account(mut, has_one = whirlpool)]
     pub position: Box<Account<'info, Position>>,
     #[account(
       constraint = position_token_account.mint == position.position_mint,
-      constraint = position_token_account.amount == 1
+      constraint = position_token_account.amount == 1,
+      constraint = position_token_account.owner == vault.key()
   )]
-    pub position_token_account: Box<Account<'info, TokenAccount>>,
+    pub position_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(mut,
-      constraint = reward_owner_account.mint == whirlpool.reward_infos[reward_index as usize].mint
+      constraint = reward_owner_account.mint == whirlpool.reward_infos[reward_index as usize].mint,
+      constraint = reward_owner_account.owner == vault.key()
   )]
-    pub reward_owner_account: Box<Account<'info, TokenAccount>>,
+    pub reward_owner_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(mut, address = whirlpool.reward_infos[reward_index as usize].vault)]
-    pub reward_vault: Box<Account<'info, TokenAccount>>,
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(address = token::ID)]
-    pub token_program: Program<'info, Token>,
+    #[account(
+      address = whirlpool.reward_infos[reward_index as usize].mint,
+      mint::token_program = reward_token_program
+  )]
+    pub reward_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub reward_token_program: Interface<'info, TokenInterface>,
+    pub memo_program: Program<'info, Memo>,
 }
 
 pub fn collect_reward_handler(ctx: Context<ProxyCollectReward>, reward_index: u8) -> Result<()> {
     let cpi_program = ctx.accounts.whirlpool_program.to_account_info();
 
-    let cpi_accounts = whirlpool_cpi::cpi::accounts::CollectReward {
+    let cpi_accounts = whirlpool_cpi::cpi::accounts::CollectRewardV2 {
         whirlpool: ctx.accounts.whirlpool.to_account_info(),
-        position_authority: ctx.accounts.position_authority.to_account_info(),
+        position_authority: ctx.accounts.vault.to_account_info(),
         position: ctx.accounts.position.to_account_info(),
         position_token_account: ctx.accounts.position_token_account.to_account_info(),
         reward_owner_account: ctx.accounts.reward_owner_account.to_account_info(),
+        reward_mint: ctx.accounts.reward_mint.to_account_info(),
         reward_vault: ctx.accounts.reward_vault.to_account_info(),
-        token_program: ctx.accounts.token_program.to_account_info(),
+        reward_token_program: ctx.accounts.reward_token_program.to_account_info(),
+        memo_program: ctx.accounts.memo_program.to_account_info(),
     };
 
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    let vault_seeds = ctx.accounts.vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
 
     // execute CPI
-    msg!("CPI: whirlpool collect_reward instruction");
-    whirlpool_cpi::cpi::collect_reward(cpi_ctx, reward_index)?;
+    msg!("CPI: whirlpool collect_reward_v2 instruction");
+    whirlpool_cpi::cpi::collect_reward_v2(cpi_ctx, reward_index, None)?;
 
     Ok(())
 }
@@ -0,0 +1,91 @@
+use anchor_lang::prelude::*;
+use anchor_spl::memo::Memo;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use whirlpool_cpi::{self, program::Whirlpool as WhirlpoolProgram, state::*};
+
+use crate::liquidity_vault::CustomError;
+use crate::Vault;
+
+#[derive(Accounts)]
+pub struct ProxyDecreaseLiquidity<'info> {
+    pub whirlpool_program: Program<'info, WhirlpoolProgram>,
+
+    #[account(mut)]
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+
+    #[account(has_one = whirlpool, has_one = admin,
+      constraint = vault.position_slot(&position.key()).is_some() @ CustomError::InvalidPositionAccounts)]
+    pub vault: Account<'info, Vault>,
+    pub admin: Signer<'info>,
+
+    #[account(mut, has_one = whirlpool)]
+    pub position: Box<Account<'info, Position>>,
+    #[account(
+      constraint = position_token_account.mint == position.position_mint,
+      constraint = position_token_account.amount == 1,
+      constraint = position_token_account.owner == vault.key()
+  )]
+    pub position_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = vault.token_account_a)]
+    pub token_owner_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = vault.token_account_b)]
+    pub token_owner_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = whirlpool.token_vault_a)]
+    pub token_vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = whirlpool.token_vault_b)]
+    pub token_vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: checked by whirlpool
+    #[account(mut)]
+    pub tick_array_lower: UncheckedAccount<'info>,
+    /// CHECK: checked by whirlpool
+    #[account(mut)]
+    pub tick_array_upper: UncheckedAccount<'info>,
+
+    #[account(address = whirlpool.token_mint_a, mint::token_program = token_program_a)]
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = whirlpool.token_mint_b, mint::token_program = token_program_b)]
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub memo_program: Program<'info, Memo>,
+}
+
+pub fn decrease_liquidity_handler(
+    ctx: Context<ProxyDecreaseLiquidity>,
+    liquidity: u128,
+    token_min_a: u64,
+    token_min_b: u64,
+) -> Result<()> {
+    let cpi_program = ctx.accounts.whirlpool_program.to_account_info();
+
+    let cpi_accounts = whirlpool_cpi::cpi::accounts::ModifyLiquidityV2 {
+        whirlpool: ctx.accounts.whirlpool.to_account_info(),
+        token_program_a: ctx.accounts.token_program_a.to_account_info(),
+        token_program_b: ctx.accounts.token_program_b.to_account_info(),
+        memo_program: ctx.accounts.memo_program.to_account_info(),
+        position_authority: ctx.accounts.vault.to_account_info(),
+        position: ctx.accounts.position.to_account_info(),
+        position_token_account: ctx.accounts.position_token_account.to_account_info(),
+        token_mint_a: ctx.accounts.token_mint_a.to_account_info(),
+        token_mint_b: ctx.accounts.token_mint_b.to_account_info(),
+        token_owner_account_a: ctx.accounts.token_owner_account_a.to_account_info(),
+        token_owner_account_b: ctx.accounts.token_owner_account_b.to_account_info(),
+        token_vault_a: ctx.accounts.token_vault_a.to_account_info(),
+        token_vault_b: ctx.accounts.token_vault_b.to_account_info(),
+        tick_array_lower: ctx.accounts.tick_array_lower.to_account_info(),
+        tick_array_upper: ctx.accounts.tick_array_upper.to_account_info(),
+    };
+
+    let vault_seeds = ctx.accounts.vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+
+    // execute CPI
+    msg!("CPI: whirlpool decrease_liquidity_v2 instruction");
+    whirlpool_cpi::cpi::decrease_liquidity_v2(cpi_ctx, liquidity, token_min_a, token_min_b, None)?;
+
+    Ok(())
+}
@@ -1,11 +1,14 @@
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{self, Token, TokenAccount},
+    metadata::Metadata,
+    token::{self, Token},
+    token_2022::Token2022,
 };
-use whirlpool_cpi::{self, program::Whirlpool as WhirlpoolProgram, state::*};
+use whirlpool_cpi::{program::Whirlpool as WhirlpoolProgram, state::*};
 
-use crate::{Rebalance, Vault};
+use crate::liquidity_vault::CustomError;
+use crate::{position, Vault};
 
 #[derive(Accounts)]
 pub struct ProxyOpenPosition<'info> {
@@ -14,9 +17,6 @@ pub struct ProxyOpenPosition<'info> {
     #[account(mut)]
     pub funder: Signer<'info>,
 
-    /// CHECK: safe (the owner of position_token_account)
-    pub owner: UncheckedAccount<'info>,
-
     /// CHECK: init by whirlpool
     #[account(mut)]
     pub position: UncheckedAccount<'info>,
@@ -37,46 +37,83 @@ pub struct ProxyOpenPosition<'info> {
     pub rent: Sysvar<'info, Rent>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 
+    /// Position NFT metadata, `WithMetadata` vaults only
+    /// CHECK: init by whirlpool
     #[account(mut)]
+    pub position_metadata_account: Option<UncheckedAccount<'info>>,
+    pub metadata_program: Option<Program<'info, Metadata>>,
+    /// CHECK: checked by whirlpool
+    pub metadata_update_auth: Option<UncheckedAccount<'info>>,
+    /// Token-2022 positions only
+    pub token_2022_program: Option<Program<'info, Token2022>>,
+
+    #[account(mut, has_one = whirlpool, has_one = admin)]
     pub vault: Account<'info, Vault>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    #[account(mut)]
-    pub user_lp_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub vault_lp_token_account: Account<'info, TokenAccount>,
+    pub admin: Signer<'info>,
 }
 
 pub fn open_position_handler(
     ctx: Context<ProxyOpenPosition>,
+    slot: u8,
     tick_lower_index: i32,
     tick_upper_index: i32,
 ) -> Result<()> {
-    let cpi_program = ctx.accounts.whirlpool_program.to_account_info();
+    // a shut down vault only pays out through `withdraw_idle`, which needs every slot closed
+    require!(!ctx.accounts.vault.is_shut_down, CustomError::VaultShutDown);
+    let slot = slot as usize;
+    let vault_position = ctx
+        .accounts
+        .vault
+        .positions
+        .get(slot)
+        .ok_or(CustomError::InvalidPositionSlot)?;
+    require!(!vault_position.is_open(), CustomError::PositionAlreadyOpen);
 
-    let cpi_accounts = whirlpool_cpi::cpi::accounts::OpenPosition {
-        funder: ctx.accounts.funder.to_account_info(),
-        owner: ctx.accounts.owner.to_account_info(),
-        position: ctx.accounts.position.to_account_info(),
-        position_mint: ctx.accounts.position_mint.to_account_info(),
-        position_token_account: ctx.accounts.position_token_account.to_account_info(),
-        whirlpool: ctx.accounts.whirlpool.to_account_info(),
-        token_program: ctx.accounts.token_program.to_account_info(),
-        system_program: ctx.accounts.system_program.to_account_info(),
-        rent: ctx.accounts.rent.to_account_info(),
-        associated_token_program: ctx.accounts.associated_token_program.to_account_info(),
-    };
-
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-
-    // execute CPI
-    msg!("CPI: whirlpool open_position instruction");
-    whirlpool_cpi::cpi::open_position(
-        cpi_ctx,
-        whirlpool_cpi::state::OpenPositionBumps { position_bump: 0 }, // passed bump is no longer used
+    position::open_position(
+        &ctx.accounts.vault,
+        position::OpenPositionAccounts {
+            whirlpool_program: ctx.accounts.whirlpool_program.to_account_info(),
+            funder: ctx.accounts.funder.to_account_info(),
+            position: ctx.accounts.position.to_account_info(),
+            position_mint: ctx.accounts.position_mint.to_account_info(),
+            position_token_account: ctx.accounts.position_token_account.to_account_info(),
+            whirlpool: ctx.accounts.whirlpool.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+            associated_token_program: ctx.accounts.associated_token_program.to_account_info(),
+            position_metadata_account: ctx
+                .accounts
+                .position_metadata_account
+                .as_ref()
+                .map(|account| account.to_account_info()),
+            metadata_program: ctx
+                .accounts
+                .metadata_program
+                .as_ref()
+                .map(|program| program.to_account_info()),
+            metadata_update_auth: ctx
+                .accounts
+                .metadata_update_auth
+                .as_ref()
+                .map(|account| account.to_account_info()),
+            token_2022_program: ctx
+                .accounts
+                .token_2022_program
+                .as_ref()
+                .map(|program| program.to_account_info()),
+        },
         tick_lower_index,
         tick_upper_index,
     )?;
 
+    // track the new position in its slot
+    ctx.accounts.vault.positions[slot].open(
+        ctx.accounts.position.key(),
+        ctx.accounts.position_mint.key(),
+        tick_lower_index,
+        tick_upper_index,
+    );
+
     Ok(())
 }
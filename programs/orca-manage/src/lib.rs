@@ -2,74 +2,460 @@ use anchor_lang::prelude::*;
 
 declare_id!("F2GMv5BTFvvJofgkx8iMrNGT8K6BDm7UDYCqPZARM6Rq");
 
-use anchor_lang::system_program::{transfer, Transfer};
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{self, Mint, Token, TokenAccount},
+    memo::Memo,
+    metadata::{
+        create_metadata_accounts_v3, mpl_token_metadata::types::DataV2, CreateMetadataAccountsV3,
+        Metadata,
+    },
+    token::{self, Token},
+    token_2022::Token2022,
+    token_interface::{self, Mint, TokenAccount, TokenInterface},
 };
 
 use whirlpool_cpi::{self, program::Whirlpool as WhirlpoolProgram, state::*};
 
+pub mod fees;
+pub mod instructions;
+pub mod math;
+pub mod nav;
+pub mod position;
+pub mod strategy;
+pub mod transfer_fee;
+
+use fees::FeeConfig;
+use instructions::*;
+use math::{full_math, liquidity_math, share_math, tick_math};
+use position::{PositionKind, PositionLayer, VaultPosition};
+use strategy::RebalanceTrigger;
+
 #[program]
 pub mod liquidity_vault {
     use super::*;
 
-    pub fn initialize_vault(ctx: Context<InitializeVault>) -> Result<()> {
+    /// Creates the registry listing every vault run by `authority`.
+    pub fn initialize_registry(ctx: Context<InitializeRegistry>) -> Result<()> {
+        let registry = &mut ctx.accounts.registry;
+        registry.bump = ctx.bumps.registry;
+        registry.authority = ctx.accounts.authority.key();
+        registry.vaults = Vec::new();
+        Ok(())
+    }
+
+    /// `strategy_id` tells apart vaults of the same creator on the same whirlpool, `layers` are
+    /// the range policies and weights of the vault's positions `rebalance` follows and
+    /// `rebalance_trigger` when it may run.
+    /// `min_deposit_value` is the smallest deposit accepted, valued in token B.
+    /// `share_decimals` sets the decimals of the vault share mint created here.
+    /// The vault is listed in the creator's registry, which `initialize_registry` must have
+    /// created.
+    pub fn initialize_vault(
+        ctx: Context<InitializeVault>,
+        strategy_id: u16,
+        layers: Vec<PositionLayer>,
+        rebalance_trigger: RebalanceTrigger,
+        min_deposit_value: u64,
+        _share_decimals: u8,
+        position_kind: PositionKind,
+    ) -> Result<()> {
+        PositionLayer::validate_layers(&layers)?;
+        rebalance_trigger.validate()?;
+        let registry = &mut ctx.accounts.registry;
+        require!(
+            registry.vaults.len() < VaultRegistry::MAX_VAULTS,
+            CustomError::RegistryFull
+        );
+        registry.vaults.push(ctx.accounts.vault.key());
+
         let vault = &mut ctx.accounts.vault;
         vault.bump = ctx.bumps.vault;
-        vault.lp_token_account = ctx.accounts.lp_token_account.key();
-        vault.total_lp_tokens = 0;
+        vault.creator = ctx.accounts.user.key();
+        vault.admin = ctx.accounts.user.key();
+        vault.keepers = Vec::new();
+        vault.permissionless = false;
+        vault.keeper_fee_bps = 0;
+        vault.max_swap_slippage_bps = Vault::DEFAULT_MAX_SWAP_SLIPPAGE_BPS;
+        vault.fee_config = FeeConfig::default();
+        vault.pending_fee_config = FeeConfig::default();
+        vault.pending_fee_config_ts = 0;
+        vault.last_fee_accrual_ts = Clock::get()?.unix_timestamp;
+        vault.strategy_id_seed = strategy_id.to_le_bytes();
+        vault.rebalance_trigger = rebalance_trigger;
+        vault.last_rebalance_ts = 0;
         vault.total_shares = 0;
+        vault.whirlpool = ctx.accounts.whirlpool.key();
+        vault.token_account_a = ctx.accounts.vault_token_account_a.key();
+        vault.token_account_b = ctx.accounts.vault_token_account_b.key();
+        vault.positions = layers.into_iter().map(VaultPosition::new).collect();
+        vault.position_kind = position_kind;
+        vault.min_deposit_value = min_deposit_value;
+        vault.share_mint = ctx.accounts.vault_token_mint.key();
         Ok(())
     }
 
-    pub fn deposit(
-        ctx: Context<Deposit>,
-        amount: u64,
-        // tick_lower_index: i32,
-        // tick_upper_index: i32,
+    /// Attaches Metaplex metadata to the share mint so wallets can display it, optional.
+    pub fn initialize_share_metadata(
+        ctx: Context<InitializeShareMetadata>,
+        name: String,
+        symbol: String,
+        uri: String,
     ) -> Result<()> {
-        let _vault_info = ctx.accounts.vault.to_account_info();
-        let vault_lp_token_account_info = ctx.accounts.vault_lp_token_account.to_account_info();
-        let vault = &mut ctx.accounts.vault;
+        let vault_seeds = ctx.accounts.vault.signer_seeds();
+        let signer_seeds = &[&vault_seeds[..]];
 
-        let program_id = ctx.accounts.token_program.to_account_info();
+        // the vault is both mint authority and update authority of the metadata
+        let cpi_accounts = CreateMetadataAccountsV3 {
+            metadata: ctx.accounts.share_metadata.to_account_info(),
+            mint: ctx.accounts.vault_token_mint.to_account_info(),
+            mint_authority: ctx.accounts.vault.to_account_info(),
+            payer: ctx.accounts.creator.to_account_info(),
+            update_authority: ctx.accounts.vault.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.metadata_program.to_account_info(),
+            cpi_accounts,
+            signer_seeds,
+        );
 
-        let cpi_context = CpiContext::new(
-            program_id,
-            Transfer {
-                from: ctx.accounts.user_lp_token_account.to_account_info(),
-                to: vault_lp_token_account_info,
+        // execute CPI
+        msg!("CPI: token metadata create_metadata_accounts_v3 instruction");
+        create_metadata_accounts_v3(
+            cpi_ctx,
+            DataV2 {
+                name,
+                symbol,
+                uri,
+                seller_fee_basis_points: 0,
+                creators: None,
+                collection: None,
+                uses: None,
             },
+            true,
+            true,
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    /// Hands the admin role to `new_admin`.
+    pub fn set_admin(ctx: Context<UpdateVault>, new_admin: Pubkey) -> Result<()> {
+        ctx.accounts.vault.admin = new_admin;
+        Ok(())
+    }
+
+    /// Replaces the keepers allowed to rebalance and compound.
+    pub fn set_keepers(ctx: Context<UpdateVault>, keepers: Vec<Pubkey>) -> Result<()> {
+        require!(
+            keepers.len() <= MAX_VAULT_KEEPERS,
+            CustomError::TooManyKeepers
         );
-        // from user's lp token account to vault's lp token account (specific to a single token pair)
-        transfer(cpi_context, amount)?;
+        ctx.accounts.vault.keepers = keepers;
+        Ok(())
+    }
 
-        vault.total_lp_tokens += amount;
+    /// Opens rebalance and compound to any caller, or restricts them to the admin and keepers.
+    pub fn set_permissionless(ctx: Context<UpdateVault>, permissionless: bool) -> Result<()> {
+        ctx.accounts.vault.permissionless = permissionless;
+        Ok(())
+    }
 
-        // Calculate shares to issue to the user based on the amount deposited
-        let shares = if vault.total_shares == 0 {
-            amount
-        } else {
-            (amount as u128 * vault.total_shares as u128 / vault.total_lp_tokens as u128) as u64
-        };
+    /// Sets the share of harvested fees paid to whoever runs rebalance or compound.
+    pub fn set_keeper_fee(ctx: Context<UpdateVault>, keeper_fee_bps: u16) -> Result<()> {
+        require!(
+            keeper_fee_bps <= Vault::MAX_KEEPER_FEE_BPS,
+            CustomError::KeeperFeeTooHigh
+        );
+        ctx.accounts.vault.keeper_fee_bps = keeper_fee_bps;
+        Ok(())
+    }
+
+    /// Sets the worst swap price, versus the pool price, rebalance and compound accept.
+    pub fn set_max_swap_slippage(
+        ctx: Context<UpdateVault>,
+        max_swap_slippage_bps: u16,
+    ) -> Result<()> {
+        require!(
+            max_swap_slippage_bps <= BPS_DENOMINATOR,
+            CustomError::InvalidBps
+        );
+        ctx.accounts.vault.max_swap_slippage_bps = max_swap_slippage_bps;
+        Ok(())
+    }
+
+    /// Queues new vault fees, which `apply_fee_config` can apply once the timelock has passed.
+    /// Their treasury has to be an existing share token account.
+    pub fn propose_fee_config(ctx: Context<ProposeFeeConfig>, fee_config: FeeConfig) -> Result<()> {
+        fee_config.validate()?;
+        let vault = &mut ctx.accounts.vault;
+        vault.pending_fee_config = fee_config;
+        vault.pending_fee_config_ts = Clock::get()?
+            .unix_timestamp
+            .checked_add(fees::FEE_TIMELOCK_SECONDS)
+            .ok_or_else(|| error!(CustomError::MathOverflow))?;
+        Ok(())
+    }
+
+    /// Applies the queued fees once their timelock has passed, charging the management fee due
+    /// under the current ones first.
+    pub fn apply_fee_config(ctx: Context<ApplyFeeConfig>) -> Result<()> {
+        let vault = &ctx.accounts.vault;
+        require!(
+            vault.pending_fee_config_ts != 0,
+            CustomError::NoPendingFeeConfig
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= vault.pending_fee_config_ts,
+            CustomError::FeeTimelockNotElapsed
+        );
+
+        // nothing was harvested, only the management fee is due
+        accrue_fees(
+            &mut ctx.accounts.vault,
+            ctx.accounts.vault_token_mint.to_account_info(),
+            ctx.accounts
+                .treasury_shares_account
+                .as_ref()
+                .map(|treasury| treasury.to_account_info()),
+            ctx.accounts.share_token_program.to_account_info(),
+            0,
+            0,
+        )?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.fee_config = vault.pending_fee_config;
+        vault.pending_fee_config = FeeConfig::default();
+        vault.pending_fee_config_ts = 0;
+        Ok(())
+    }
+
+    /// Changes when `rebalance` may run.
+    pub fn set_rebalance_trigger(
+        ctx: Context<UpdateVault>,
+        rebalance_trigger: RebalanceTrigger,
+    ) -> Result<()> {
+        rebalance_trigger.validate()?;
+        ctx.accounts.vault.rebalance_trigger = rebalance_trigger;
+        Ok(())
+    }
 
-        vault.total_shares += shares;
+    /// Adds the user's token A/B to the vault's positions, split by their weights, and mints shares
+    /// for the liquidity added.
+    /// `amount_a_max`/`amount_b_max` cap what leaves the user, Token-2022 transfer fees included.
+    /// The first deposit gives up [`share_math::LOCKED_SHARES`] of its shares.
+    ///
+    /// Remaining accounts: the vault's open positions, see [`position::load_positions`].
+    pub fn deposit_tokens<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DepositTokens<'info>>,
+        amount_a_max: u64,
+        amount_b_max: u64,
+        min_shares: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.vault.is_shut_down, CustomError::VaultShutDown);
+        let (mut positions, _) =
+            position::load_positions(&ctx.accounts.vault, ctx.remaining_accounts)?;
+        require!(!positions.is_empty(), CustomError::NoOpenPosition);
+        for position in positions.iter_mut() {
+            // update fees and rewards so the owed amounts are current
+            position::update_fees_and_rewards(
+                ctx.accounts.whirlpool_program.to_account_info(),
+                ctx.accounts.whirlpool.to_account_info(),
+                position,
+            )?;
+        }
+
+        // charge the management fee due so the deposit is priced against the current supply
+        accrue_fees(
+            &mut ctx.accounts.vault,
+            ctx.accounts.vault_token_mint.to_account_info(),
+            ctx.accounts
+                .treasury_shares_account
+                .as_ref()
+                .map(|treasury| treasury.to_account_info()),
+            ctx.accounts.share_token_program.to_account_info(),
+            0,
+            0,
+        )?;
+
+        let whirlpool = &ctx.accounts.whirlpool;
+        let vault = &ctx.accounts.vault;
+        let nav_before = nav::get_vault_nav(
+            whirlpool,
+            positions.iter().map(|position| &*position.position),
+            ctx.accounts.vault_token_account_a.amount,
+            ctx.accounts.vault_token_account_b.amount,
+        )?;
+
+        // Largest liquidity the user's maximum amounts can back in each position's range, net of
+        // the transfer fees taken from the user into the vault and from the vault into the pool
+        let token_mint_a = ctx.accounts.token_mint_a.to_account_info();
+        let token_mint_b = ctx.accounts.token_mint_b.to_account_info();
+        let net_amount_a_max = transfer_fee::get_amount_after_fee(
+            &token_mint_a,
+            transfer_fee::get_amount_after_fee(&token_mint_a, amount_a_max)?,
+        )?;
+        let net_amount_b_max = transfer_fee::get_amount_after_fee(
+            &token_mint_b,
+            transfer_fee::get_amount_after_fee(&token_mint_b, amount_b_max)?,
+        )?;
+        let ranges = positions
+            .iter()
+            .map(|position| vault.positions[position.slot].weighted_range())
+            .collect::<Result<Vec<_>>>()?;
+        let amounts_max = nav::split_amounts(
+            net_amount_a_max,
+            net_amount_b_max,
+            whirlpool.sqrt_price,
+            &ranges,
+        )?;
+
+        // Amounts each position's pool takes for its liquidity, what the vault sends for them to
+        // arrive and what the user sends for the vault to receive that
+        let mut deposits = Vec::with_capacity(positions.len());
+        let (mut amount_a, mut amount_b) = (0u64, 0u64);
+        let (mut vault_amount_a, mut vault_amount_b) = (0u64, 0u64);
+        for (range, (position_amount_a_max, position_amount_b_max)) in
+            ranges.iter().zip(amounts_max)
+        {
+            let liquidity = liquidity_math::get_liquidity_from_amounts(
+                whirlpool.sqrt_price,
+                range.sqrt_price_lower_x64,
+                range.sqrt_price_upper_x64,
+                position_amount_a_max,
+                position_amount_b_max,
+            )?;
+            let (position_amount_a, position_amount_b) =
+                liquidity_math::get_amounts_from_liquidity(
+                    whirlpool.sqrt_price,
+                    range.sqrt_price_lower_x64,
+                    range.sqrt_price_upper_x64,
+                    liquidity,
+                    true,
+                )?;
+            let position_vault_amount_a =
+                transfer_fee::get_amount_before_fee(&token_mint_a, position_amount_a)?;
+            let position_vault_amount_b =
+                transfer_fee::get_amount_before_fee(&token_mint_b, position_amount_b)?;
+            amount_a += position_amount_a;
+            amount_b += position_amount_b;
+            vault_amount_a += position_vault_amount_a;
+            vault_amount_b += position_vault_amount_b;
+            deposits.push((liquidity, position_vault_amount_a, position_vault_amount_b));
+        }
+        require!(
+            deposits.iter().any(|&(liquidity, _, _)| liquidity > 0),
+            CustomError::ZeroLiquidity
+        );
+        let user_amount_a = transfer_fee::get_amount_before_fee(&token_mint_a, vault_amount_a)?;
+        let user_amount_b = transfer_fee::get_amount_before_fee(&token_mint_b, vault_amount_b)?;
+        require!(
+            user_amount_a <= amount_a_max && user_amount_b <= amount_b_max,
+            CustomError::SlippageExceeded
+        );
+
+        // Shares are issued pro-rata to the value the pool received relative to the vault NAV
+        let deposit_value = (amount_b as u128)
+            .checked_add(nav::value_in_token_b(
+                amount_a as u128,
+                whirlpool.sqrt_price,
+            )?)
+            .ok_or_else(|| error!(CustomError::MathOverflow))?;
+        require!(
+            deposit_value >= vault.min_deposit_value as u128,
+            CustomError::DepositTooSmall
+        );
+        let shares =
+            share_math::get_shares_for_value(deposit_value, vault.total_shares, nav_before.value)?;
+        let (user_shares, _) = share_math::lock_first_deposit_shares(shares, vault.total_shares)?;
+        require!(user_shares > 0, CustomError::InvalidSharesAmount);
+        require!(user_shares >= min_shares, CustomError::SlippageExceeded);
+
+        // Move the required tokens from the user into the vault's token accounts
+        for (from, to, mint, token_program, amount) in [
+            (
+                &ctx.accounts.user_token_account_a,
+                &ctx.accounts.vault_token_account_a,
+                &ctx.accounts.token_mint_a,
+                &ctx.accounts.token_program_a,
+                user_amount_a,
+            ),
+            (
+                &ctx.accounts.user_token_account_b,
+                &ctx.accounts.vault_token_account_b,
+                &ctx.accounts.token_mint_b,
+                &ctx.accounts.token_program_b,
+                user_amount_b,
+            ),
+        ] {
+            if amount == 0 {
+                continue;
+            }
+            transfer_tokens(
+                token_program.to_account_info(),
+                from.to_account_info(),
+                to.to_account_info(),
+                mint,
+                ctx.accounts.user.to_account_info(),
+                &[],
+                amount,
+            )?;
+        }
+
+        let vault_seeds = ctx.accounts.vault.signer_seeds();
+        let signer_seeds = &[&vault_seeds[..]];
+
+        // increase liquidity
+        let pool = position::PoolAccounts {
+            whirlpool_program: ctx.accounts.whirlpool_program.to_account_info(),
+            whirlpool: ctx.accounts.whirlpool.to_account_info(),
+            vault: ctx.accounts.vault.to_account_info(),
+            token_mint_a,
+            token_mint_b,
+            token_owner_account_a: ctx.accounts.vault_token_account_a.to_account_info(),
+            token_owner_account_b: ctx.accounts.vault_token_account_b.to_account_info(),
+            token_vault_a: ctx.accounts.token_vault_a.to_account_info(),
+            token_vault_b: ctx.accounts.token_vault_b.to_account_info(),
+            token_program_a: ctx.accounts.token_program_a.to_account_info(),
+            token_program_b: ctx.accounts.token_program_b.to_account_info(),
+            memo_program: ctx.accounts.memo_program.to_account_info(),
+        };
+        for (position, (liquidity, position_vault_amount_a, position_vault_amount_b)) in
+            positions.iter().zip(deposits)
+        {
+            if liquidity == 0 {
+                continue;
+            }
+            pool.increase_liquidity(
+                position,
+                signer_seeds,
+                liquidity,
+                position_vault_amount_a,
+                position_vault_amount_b,
+            )?;
+        }
 
         // Mint vault shares to user
-        let cpi_accounts_vault = token::MintTo {
+        let cpi_accounts_vault = token_interface::MintTo {
             mint: ctx.accounts.vault_token_mint.to_account_info(),
             to: ctx.accounts.user_shares_account.to_account_info(),
             authority: ctx.accounts.vault.to_account_info(),
         };
-        let cpi_context_vault = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
+        let cpi_context_vault = CpiContext::new_with_signer(
+            ctx.accounts.share_token_program.to_account_info(),
             cpi_accounts_vault,
+            signer_seeds,
         );
-        token::mint_to(cpi_context_vault, shares)?;
+        token_interface::mint_to(cpi_context_vault, user_shares)?;
 
-        // not needed if user already has lp tokens (open position)
-        // deposit_handler(ctx, tick_lower_index, tick_upper_index)?;
+        // locked shares join the supply without being minted to anyone
+        let vault = &mut ctx.accounts.vault;
+        vault.total_shares = vault
+            .total_shares
+            .checked_add(shares)
+            .ok_or_else(|| error!(CustomError::MathOverflow))?;
 
         Ok(())
     }
@@ -78,420 +464,1907 @@ pub mod liquidity_vault {
     pub enum CustomError {
         #[msg("Invalid shares amount")]
         InvalidSharesAmount,
+        #[msg("Math overflow")]
+        MathOverflow,
+        #[msg("Division by zero")]
+        DivideByZero,
+        #[msg("Min price must be lower than max price")]
+        InvalidPriceRange,
+        #[msg("Tick index out of bounds")]
+        InvalidTickIndex,
+        #[msg("Sqrt price out of bounds")]
+        InvalidSqrtPrice,
+        #[msg("Vault already manages an open position")]
+        PositionAlreadyOpen,
+        #[msg("Deposit amounts are too small to add liquidity")]
+        ZeroLiquidity,
+        #[msg("Slippage tolerance exceeded")]
+        SlippageExceeded,
+        #[msg("Deposit is below the vault minimum")]
+        DepositTooSmall,
+        #[msg("Vault registry is full")]
+        RegistryFull,
+        #[msg("Reward accounts do not match the whirlpool's initialized reward slots")]
+        InvalidRewardAccounts,
+        #[msg("Vault has been shut down")]
+        VaultShutDown,
+        #[msg("Vault position must be unwound first")]
+        PositionStillOpen,
+        #[msg("Basis points above 100%")]
+        InvalidBps,
+        #[msg("Swap tick arrays and oracle are required to swap")]
+        MissingSwapAccounts,
+        #[msg("Invalid strategy parameters")]
+        InvalidStrategy,
+        #[msg("Invalid rebalance trigger parameters")]
+        InvalidRebalanceTrigger,
+        #[msg("Vault was rebalanced too recently")]
+        RebalanceCooldown,
+        #[msg("Signer is not allowed to perform this operation")]
+        Unauthorized,
+        #[msg("Too many keepers")]
+        TooManyKeepers,
+        #[msg("Keeper fee above the maximum")]
+        KeeperFeeTooHigh,
+        #[msg("Fee above the maximum")]
+        FeeTooHigh,
+        #[msg("No fee change is pending")]
+        NoPendingFeeConfig,
+        #[msg("Fee change timelock has not elapsed")]
+        FeeTimelockNotElapsed,
+        #[msg("Treasury account is required to mint fees")]
+        MissingTreasury,
+        #[msg("Accounts required by the vault's position kind are missing")]
+        MissingPositionAccounts,
+        #[msg("Position layers must be within the maximum count and their weights add up to 100%")]
+        InvalidPositionLayers,
+        #[msg("Position accounts do not match the vault's positions")]
+        InvalidPositionAccounts,
+        #[msg("Vault has no such position slot")]
+        InvalidPositionSlot,
+        #[msg("Vault has no open position")]
+        NoOpenPosition,
+        #[msg("Swap slippage above the vault maximum")]
+        SwapSlippageTooHigh,
+        #[msg("Token A/B count towards the vault NAV and cannot be swept")]
+        PoolTokenNotSweepable,
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>, shares: u64) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
-        let total_shares = vault.total_shares;
-        let total_lp_tokens = vault.total_lp_tokens;
-
+    /// Burns shares and pays out their share of every position's liquidity and of the idle
+    /// balances.
+    ///
+    /// Owed fees are collected into the vault first and charged the vault's fees like a compound,
+    /// so taking the same fraction of every NAV component pays out `shares` worth of the vault
+    /// NAV. Owed rewards are not part of the NAV and stay with the vault until harvested.
+    ///
+    /// Remaining accounts: the vault's open positions, see [`position::load_positions`].
+    pub fn withdraw_tokens<'info>(
+        ctx: Context<'_, '_, 'info, 'info, WithdrawTokens<'info>>,
+        shares: u64,
+        min_a: u64,
+        min_b: u64,
+    ) -> Result<()> {
         require!(shares > 0, CustomError::InvalidSharesAmount);
+
+        let (mut positions, _) =
+            position::load_positions(&ctx.accounts.vault, ctx.remaining_accounts)?;
+        let pool = position::PoolAccounts {
+            whirlpool_program: ctx.accounts.whirlpool_program.to_account_info(),
+            whirlpool: ctx.accounts.whirlpool.to_account_info(),
+            vault: ctx.accounts.vault.to_account_info(),
+            token_mint_a: ctx.accounts.token_mint_a.to_account_info(),
+            token_mint_b: ctx.accounts.token_mint_b.to_account_info(),
+            token_owner_account_a: ctx.accounts.vault_token_account_a.to_account_info(),
+            token_owner_account_b: ctx.accounts.vault_token_account_b.to_account_info(),
+            token_vault_a: ctx.accounts.token_vault_a.to_account_info(),
+            token_vault_b: ctx.accounts.token_vault_b.to_account_info(),
+            token_program_a: ctx.accounts.token_program_a.to_account_info(),
+            token_program_b: ctx.accounts.token_program_b.to_account_info(),
+            memo_program: ctx.accounts.memo_program.to_account_info(),
+        };
+        let vault_seeds = ctx.accounts.vault.signer_seeds();
+        let signer_seeds = &[&vault_seeds[..]];
+
+        for position in positions.iter_mut() {
+            // update fees and rewards so the owed amounts are current, then collect fees
+            position::update_fees_and_rewards(
+                ctx.accounts.whirlpool_program.to_account_info(),
+                ctx.accounts.whirlpool.to_account_info(),
+                position,
+            )?;
+            pool.collect_fees(position, signer_seeds)?;
+        }
+        let balance_a_before = ctx.accounts.vault_token_account_a.amount;
+        let balance_b_before = ctx.accounts.vault_token_account_b.amount;
+        ctx.accounts.vault_token_account_a.reload()?;
+        ctx.accounts.vault_token_account_b.reload()?;
+
+        // charge the vault's fees on the fees just collected, and the management fee due, so the
+        // shares are redeemed against the current supply
+        let nav = nav::get_vault_nav(
+            &ctx.accounts.whirlpool,
+            positions.iter().map(|position| &*position.position),
+            ctx.accounts.vault_token_account_a.amount,
+            ctx.accounts.vault_token_account_b.amount,
+        )?;
+        let fees_a = ctx.accounts.vault_token_account_a.amount - balance_a_before;
+        let fees_b = ctx.accounts.vault_token_account_b.amount - balance_b_before;
+        let harvested_value = (fees_b as u128)
+            .checked_add(nav::value_in_token_b(
+                fees_a as u128,
+                ctx.accounts.whirlpool.sqrt_price,
+            )?)
+            .ok_or_else(|| error!(CustomError::MathOverflow))?;
+        accrue_fees(
+            &mut ctx.accounts.vault,
+            ctx.accounts.vault_token_mint.to_account_info(),
+            ctx.accounts
+                .treasury_shares_account
+                .as_ref()
+                .map(|treasury| treasury.to_account_info()),
+            ctx.accounts.share_token_program.to_account_info(),
+            harvested_value,
+            nav.value,
+        )?;
+
+        // accrue_fees borrowed the vault mutably, take its seeds again
+        let vault_seeds = ctx.accounts.vault.signer_seeds();
+        let signer_seeds = &[&vault_seeds[..]];
+        let total_shares = ctx.accounts.vault.total_shares;
         require!(shares <= total_shares, CustomError::InvalidSharesAmount);
 
-        // Calculate the LP tokens to withdraw based on shares
-        let amount = (shares as u128 * total_lp_tokens as u128 / total_shares as u128) as u64;
+        // Pro-rata part of every position and of the tokens sitting idle in the vault
+        let idle_a = share_math::get_amount_for_shares(
+            ctx.accounts.vault_token_account_a.amount as u128,
+            shares,
+            total_shares,
+        )? as u64;
+        let idle_b = share_math::get_amount_for_shares(
+            ctx.accounts.vault_token_account_b.amount as u128,
+            shares,
+            total_shares,
+        )? as u64;
+        let balance_a_before = ctx.accounts.vault_token_account_a.amount;
+        let balance_b_before = ctx.accounts.vault_token_account_b.amount;
+
+        for position in &positions {
+            let liquidity = share_math::get_amount_for_shares(
+                position.position.liquidity,
+                shares,
+                total_shares,
+            )?;
+            if liquidity == 0 {
+                continue;
+            }
+            // min_a/min_b are checked against the total below
+            pool.decrease_liquidity(position, signer_seeds, liquidity, 0, 0)?;
+        }
+
+        ctx.accounts.vault_token_account_a.reload()?;
+        ctx.accounts.vault_token_account_b.reload()?;
+        let amount_a = idle_a + (ctx.accounts.vault_token_account_a.amount - balance_a_before);
+        let amount_b = idle_b + (ctx.accounts.vault_token_account_b.amount - balance_b_before);
+        // min_a/min_b bound what reaches the user, after transfer fees
+        let received_a = transfer_fee::get_amount_after_fee(
+            &ctx.accounts.token_mint_a.to_account_info(),
+            amount_a,
+        )?;
+        let received_b = transfer_fee::get_amount_after_fee(
+            &ctx.accounts.token_mint_b.to_account_info(),
+            amount_b,
+        )?;
+        require!(
+            received_a >= min_a && received_b >= min_b,
+            CustomError::SlippageExceeded
+        );
 
         // Burn the user's shares
-        let cpi_accounts = token::Burn {
+        let cpi_accounts = token_interface::Burn {
             mint: ctx.accounts.vault_token_mint.to_account_info(),
             from: ctx.accounts.user_shares_account.to_account_info(),
             authority: ctx.accounts.user.to_account_info(),
         };
-        let cpi_context =
-            CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-        token::burn(cpi_context, shares)?;
+        let cpi_context = CpiContext::new(
+            ctx.accounts.share_token_program.to_account_info(),
+            cpi_accounts,
+        );
+        token_interface::burn(cpi_context, shares)?;
+
+        // Transfer token A/B from the vault back to the user
+        for (from, to, mint, token_program, amount) in [
+            (
+                &ctx.accounts.vault_token_account_a,
+                &ctx.accounts.user_token_account_a,
+                &ctx.accounts.token_mint_a,
+                &ctx.accounts.token_program_a,
+                amount_a,
+            ),
+            (
+                &ctx.accounts.vault_token_account_b,
+                &ctx.accounts.user_token_account_b,
+                &ctx.accounts.token_mint_b,
+                &ctx.accounts.token_program_b,
+                amount_b,
+            ),
+        ] {
+            if amount == 0 {
+                continue;
+            }
+            transfer_tokens(
+                token_program.to_account_info(),
+                from.to_account_info(),
+                to.to_account_info(),
+                mint,
+                ctx.accounts.vault.to_account_info(),
+                signer_seeds,
+                amount,
+            )?;
+        }
 
-        // Update vault's total shares and LP token balance
+        let vault = &mut ctx.accounts.vault;
         vault.total_shares -= shares;
-        vault.total_lp_tokens -= amount;
 
-        // Transfer LP tokens back to the user
+        Ok(())
+    }
+
+    /// Collects the positions' fees and rewards into the vault and re-adds everything the vault
+    /// holds in token A/B as liquidity, swapping first to the ratio the ranges need and splitting
+    /// it between the positions by weight. Only the admin and keepers swap, and only they need to
+    /// pass the swap accounts; other callers re-add what fits the ranges as is.
+    ///
+    /// Remaining accounts: the vault's open positions (see [`position::load_positions`]), then the
+    /// reward accounts (see [`collect_rewards`]).
+    pub fn compound<'info>(ctx: Context<'_, '_, 'info, 'info, Compound<'info>>) -> Result<()> {
+        let (mut positions, reward_accounts) =
+            position::load_positions(&ctx.accounts.vault, ctx.remaining_accounts)?;
+        let pool = position::PoolAccounts {
+            whirlpool_program: ctx.accounts.whirlpool_program.to_account_info(),
+            whirlpool: ctx.accounts.whirlpool.to_account_info(),
+            vault: ctx.accounts.vault.to_account_info(),
+            token_mint_a: ctx.accounts.token_mint_a.to_account_info(),
+            token_mint_b: ctx.accounts.token_mint_b.to_account_info(),
+            token_owner_account_a: ctx.accounts.vault_token_account_a.to_account_info(),
+            token_owner_account_b: ctx.accounts.vault_token_account_b.to_account_info(),
+            token_vault_a: ctx.accounts.token_vault_a.to_account_info(),
+            token_vault_b: ctx.accounts.token_vault_b.to_account_info(),
+            token_program_a: ctx.accounts.token_program_a.to_account_info(),
+            token_program_b: ctx.accounts.token_program_b.to_account_info(),
+            memo_program: ctx.accounts.memo_program.to_account_info(),
+        };
+        let vault_seeds = ctx.accounts.vault.signer_seeds();
+        let signer_seeds = &[&vault_seeds[..]];
+
+        for position in positions.iter_mut() {
+            // update fees and rewards so the owed amounts are current, then collect fees
+            position::update_fees_and_rewards(
+                ctx.accounts.whirlpool_program.to_account_info(),
+                ctx.accounts.whirlpool.to_account_info(),
+                position,
+            )?;
+            pool.collect_fees(position, signer_seeds)?;
+        }
+        let balance_a_before = ctx.accounts.vault_token_account_a.amount;
+        let balance_b_before = ctx.accounts.vault_token_account_b.amount;
+        ctx.accounts.vault_token_account_a.reload()?;
+        ctx.accounts.vault_token_account_b.reload()?;
+
+        // the caller is paid for the transaction out of the fees it harvested
+        let [fees_a, fees_b] = pay_keeper_fee(
+            &ctx.accounts.vault,
+            [
+                KeeperFeePayout {
+                    token_program: ctx.accounts.token_program_a.to_account_info(),
+                    mint: &ctx.accounts.token_mint_a,
+                    from: ctx.accounts.vault_token_account_a.to_account_info(),
+                    to: ctx.accounts.caller_token_account_a.to_account_info(),
+                    fees: ctx.accounts.vault_token_account_a.amount - balance_a_before,
+                },
+                KeeperFeePayout {
+                    token_program: ctx.accounts.token_program_b.to_account_info(),
+                    mint: &ctx.accounts.token_mint_b,
+                    from: ctx.accounts.vault_token_account_b.to_account_info(),
+                    to: ctx.accounts.caller_token_account_b.to_account_info(),
+                    fees: ctx.accounts.vault_token_account_b.amount - balance_b_before,
+                },
+            ],
+        )?;
+
+        // collect rewards, the ones paid in token A or B land in the accounts compounded below
+        for position in positions.iter_mut() {
+            collect_rewards(
+                ctx.accounts.whirlpool_program.to_account_info(),
+                &ctx.accounts.whirlpool,
+                &ctx.accounts.vault,
+                position.position.to_account_info(),
+                position.position_token_account.clone(),
+                ctx.accounts.memo_program.to_account_info(),
+                reward_accounts,
+            )?;
+            position.position.reload()?;
+        }
+        ctx.accounts.vault_token_account_a.reload()?;
+        ctx.accounts.vault_token_account_b.reload()?;
+
+        // charge the vault's fees on what was just harvested
+        let nav = nav::get_vault_nav(
+            &ctx.accounts.whirlpool,
+            positions.iter().map(|position| &*position.position),
+            ctx.accounts.vault_token_account_a.amount,
+            ctx.accounts.vault_token_account_b.amount,
+        )?;
+        let harvested_value = (fees_b as u128)
+            .checked_add(nav::value_in_token_b(
+                fees_a as u128,
+                ctx.accounts.whirlpool.sqrt_price,
+            )?)
+            .ok_or_else(|| error!(CustomError::MathOverflow))?;
+        accrue_fees(
+            &mut ctx.accounts.vault,
+            ctx.accounts.vault_token_mint.to_account_info(),
+            ctx.accounts
+                .treasury_shares_account
+                .as_ref()
+                .map(|treasury| treasury.to_account_info()),
+            ctx.accounts.share_token_program.to_account_info(),
+            harvested_value,
+            nav.value,
+        )?;
+
+        // accrue_fees borrowed the vault mutably, take its seeds again
+        let vault_seeds = ctx.accounts.vault.signer_seeds();
+        let signer_seeds = &[&vault_seeds[..]];
+
+        let ranges = positions
+            .iter()
+            .map(|position| ctx.accounts.vault.positions[position.slot].weighted_range())
+            .collect::<Result<Vec<_>>>()?;
+
+        // swap the idle balances to the ratio the ranges need at the current price; the swap is
+        // bounded against the spot price, which the caller can move in the same transaction, so
+        // only the admin and keepers swap
+        let swap = if ctx.accounts.vault.is_keeper(&ctx.accounts.caller.key()) {
+            nav::get_swap_to_ratio(
+                ctx.accounts.vault_token_account_a.amount,
+                ctx.accounts.vault_token_account_b.amount,
+                ctx.accounts.whirlpool.sqrt_price,
+                &ranges,
+            )?
+        } else {
+            None
+        };
+        if let Some(swap) = swap {
+            let (Some(tick_array0), Some(tick_array1), Some(tick_array2), Some(oracle)) = (
+                ctx.accounts.swap_tick_array_0.as_ref(),
+                ctx.accounts.swap_tick_array_1.as_ref(),
+                ctx.accounts.swap_tick_array_2.as_ref(),
+                ctx.accounts.oracle.as_ref(),
+            ) else {
+                return err!(CustomError::MissingSwapAccounts);
+            };
+            let sqrt_price_limit = if swap.a_to_b {
+                tick_math::MIN_SQRT_PRICE_X64
+            } else {
+                tick_math::MAX_SQRT_PRICE_X64
+            };
+            swap_to_ratio(
+                ctx.accounts.whirlpool_program.to_account_info(),
+                whirlpool_cpi::cpi::accounts::SwapV2 {
+                    token_program_a: ctx.accounts.token_program_a.to_account_info(),
+                    token_program_b: ctx.accounts.token_program_b.to_account_info(),
+                    memo_program: ctx.accounts.memo_program.to_account_info(),
+                    token_authority: ctx.accounts.vault.to_account_info(),
+                    whirlpool: ctx.accounts.whirlpool.to_account_info(),
+                    token_mint_a: ctx.accounts.token_mint_a.to_account_info(),
+                    token_mint_b: ctx.accounts.token_mint_b.to_account_info(),
+                    token_owner_account_a: ctx.accounts.vault_token_account_a.to_account_info(),
+                    token_vault_a: ctx.accounts.token_vault_a.to_account_info(),
+                    token_owner_account_b: ctx.accounts.vault_token_account_b.to_account_info(),
+                    token_vault_b: ctx.accounts.token_vault_b.to_account_info(),
+                    tick_array_0: tick_array0.to_account_info(),
+                    tick_array_1: tick_array1.to_account_info(),
+                    tick_array_2: tick_array2.to_account_info(),
+                    oracle: oracle.to_account_info(),
+                },
+                &ctx.accounts.vault,
+                swap,
+                sqrt_price_limit,
+                ctx.accounts.vault.max_swap_slippage_bps,
+            )?;
+            ctx.accounts.whirlpool.reload()?;
+            ctx.accounts.vault_token_account_a.reload()?;
+            ctx.accounts.vault_token_account_b.reload()?;
+        }
+
+        let deployed = add_liquidity_by_weight(
+            &pool,
+            signer_seeds,
+            &positions,
+            &ranges,
+            ctx.accounts.whirlpool.sqrt_price,
+            ctx.accounts.vault_token_account_a.amount,
+            ctx.accounts.vault_token_account_b.amount,
+        )?;
+        if deployed.liquidity.iter().all(|&liquidity| liquidity == 0) {
+            msg!("Nothing to compound");
+        }
+
+        Ok(())
+    }
+
+    /// Collects every initialized reward slot of the vault's positions into vault-owned accounts.
+    /// Permissionless.
+    ///
+    /// Remaining accounts: the vault's open positions (see [`position::load_positions`]), then the
+    /// reward accounts (see [`collect_rewards`]).
+    pub fn harvest<'info>(ctx: Context<'_, '_, 'info, 'info, Harvest<'info>>) -> Result<()> {
+        let (positions, reward_accounts) =
+            position::load_positions(&ctx.accounts.vault, ctx.remaining_accounts)?;
+        for mut position in positions {
+            // update fees and rewards so the owed amounts are current
+            position::update_fees_and_rewards(
+                ctx.accounts.whirlpool_program.to_account_info(),
+                ctx.accounts.whirlpool.to_account_info(),
+                &mut position,
+            )?;
+
+            collect_rewards(
+                ctx.accounts.whirlpool_program.to_account_info(),
+                &ctx.accounts.whirlpool,
+                &ctx.accounts.vault,
+                position.position.to_account_info(),
+                position.position_token_account,
+                ctx.accounts.memo_program.to_account_info(),
+                reward_accounts,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the vault's whole balance of a reward token other than token A/B to the fee
+    /// treasury's owner. Such rewards are not part of the vault NAV, so nothing else moves them
+    /// out of the vault. Admin and keepers only.
+    pub fn sweep_reward(ctx: Context<SweepReward>) -> Result<()> {
+        let amount = ctx.accounts.vault_reward_account.amount;
+        if amount == 0 {
+            msg!("Nothing to sweep");
+            return Ok(());
+        }
+
+        let vault_seeds = ctx.accounts.vault.signer_seeds();
+        let signer_seeds = &[&vault_seeds[..]];
+        transfer_tokens(
+            ctx.accounts.reward_token_program.to_account_info(),
+            ctx.accounts.vault_reward_account.to_account_info(),
+            ctx.accounts.treasury_reward_account.to_account_info(),
+            &ctx.accounts.reward_mint,
+            ctx.accounts.vault.to_account_info(),
+            signer_seeds,
+            amount,
+        )
+    }
+
+    /// Fully unwinds the vault's positions into its token accounts and leaves the funds idle.
+    /// Deposits and rebalances stop until new positions are opened; `withdraw_idle` pays out.
+    ///
+    /// Remaining accounts: the vault's open positions (see [`position::load_positions`]), then the
+    /// reward accounts (see [`collect_rewards`]).
+    pub fn emergency_exit<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UnwindVault<'info>>,
+        min_amount_a: u64,
+        min_amount_b: u64,
+    ) -> Result<()> {
+        unwind_vault(ctx, min_amount_a, min_amount_b)
+    }
+
+    /// Unwinds the vault's positions for good: the vault stops taking deposits and holders exit
+    /// through `withdraw_idle`.
+    ///
+    /// Remaining accounts: the vault's open positions (see [`position::load_positions`]), then the
+    /// reward accounts (see [`collect_rewards`]).
+    pub fn shutdown_vault<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UnwindVault<'info>>,
+        min_amount_a: u64,
+        min_amount_b: u64,
+    ) -> Result<()> {
+        ctx.accounts.vault.is_shut_down = true;
+        unwind_vault(ctx, min_amount_a, min_amount_b)
+    }
+
+    /// Burns `shares` for their pro-rata part of the vault's idle token A/B. Only available while
+    /// the vault has no open positions, e.g. after `emergency_exit` or `shutdown_vault`.
+    pub fn withdraw_idle(
+        ctx: Context<WithdrawIdle>,
+        shares: u64,
+        min_a: u64,
+        min_b: u64,
+    ) -> Result<()> {
+        let total_shares = ctx.accounts.vault.total_shares;
+        require!(shares > 0, CustomError::InvalidSharesAmount);
+        require!(shares <= total_shares, CustomError::InvalidSharesAmount);
+        require!(
+            !ctx.accounts.vault.has_open_position(),
+            CustomError::PositionStillOpen
+        );
+
+        let amount_a = share_math::get_amount_for_shares(
+            ctx.accounts.vault_token_account_a.amount as u128,
+            shares,
+            total_shares,
+        )? as u64;
+        let amount_b = share_math::get_amount_for_shares(
+            ctx.accounts.vault_token_account_b.amount as u128,
+            shares,
+            total_shares,
+        )? as u64;
+        // min_a/min_b bound what reaches the user, after transfer fees
+        let received_a = transfer_fee::get_amount_after_fee(
+            &ctx.accounts.token_mint_a.to_account_info(),
+            amount_a,
+        )?;
+        let received_b = transfer_fee::get_amount_after_fee(
+            &ctx.accounts.token_mint_b.to_account_info(),
+            amount_b,
+        )?;
+        require!(
+            received_a >= min_a && received_b >= min_b,
+            CustomError::SlippageExceeded
+        );
+
+        // Burn the user's shares
+        let cpi_accounts = token_interface::Burn {
+            mint: ctx.accounts.vault_token_mint.to_account_info(),
+            from: ctx.accounts.user_shares_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
         let cpi_context = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            Transfer {
-                from: ctx.accounts.vault_lp_token_account.to_account_info(),
-                to: ctx.accounts.user_lp_token_account.to_account_info(),
-                // authority: ctx.accounts.vault.to_account_info(),
-            },
+            ctx.accounts.share_token_program.to_account_info(),
+            cpi_accounts,
         );
-        transfer(cpi_context, amount)?;
+        token_interface::burn(cpi_context, shares)?;
+
+        let vault_seeds = ctx.accounts.vault.signer_seeds();
+        let signer_seeds = &[&vault_seeds[..]];
+
+        // Transfer token A/B from the vault back to the user
+        for (from, to, mint, token_program, amount) in [
+            (
+                &ctx.accounts.vault_token_account_a,
+                &ctx.accounts.user_token_account_a,
+                &ctx.accounts.token_mint_a,
+                &ctx.accounts.token_program_a,
+                amount_a,
+            ),
+            (
+                &ctx.accounts.vault_token_account_b,
+                &ctx.accounts.user_token_account_b,
+                &ctx.accounts.token_mint_b,
+                &ctx.accounts.token_program_b,
+                amount_b,
+            ),
+        ] {
+            if amount == 0 {
+                continue;
+            }
+            transfer_tokens(
+                token_program.to_account_info(),
+                from.to_account_info(),
+                to.to_account_info(),
+                mint,
+                ctx.accounts.vault.to_account_info(),
+                signer_seeds,
+                amount,
+            )?;
+        }
+
+        let vault = &mut ctx.accounts.vault;
+        vault.total_shares -= shares;
 
         Ok(())
     }
 
-    pub fn rebalance(
-        ctx: Context<Rebalance>,
-        min_price: u64,
-        max_price: u64,
-        // tick_lower_index: i32,
-        // tick_upper_index: i32,
+    /// Admin only: opens a position owned by the vault in position slot `slot` when it has none,
+    /// e.g. for the first deposits or after `emergency_exit`.
+    pub fn proxy_open_position(
+        ctx: Context<ProxyOpenPosition>,
+        slot: u8,
+        tick_lower_index: i32,
+        tick_upper_index: i32,
+    ) -> Result<()> {
+        open_position_handler(ctx, slot, tick_lower_index, tick_upper_index)
+    }
+
+    /// Admin only: closes one of the vault's positions, which must hold no liquidity, fees or
+    /// rewards.
+    pub fn proxy_close_position(ctx: Context<ProxyClosePosition>) -> Result<()> {
+        close_position_handler(ctx)
+    }
+
+    /// Admin only: collects one vault position's fees into vault-owned accounts.
+    pub fn proxy_collect_fees(ctx: Context<ProxyCollectFees>) -> Result<()> {
+        collect_fees_handler(ctx)
+    }
+
+    /// Admin only: collects one reward slot of a vault position into a vault-owned account.
+    pub fn proxy_collect_reward(ctx: Context<ProxyCollectReward>, reward_index: u8) -> Result<()> {
+        collect_reward_handler(ctx, reward_index)
+    }
+
+    /// Admin only: adds `liquidity` to a vault position from the vault's token accounts,
+    /// spending at most `token_max_a`/`token_max_b`.
+    pub fn proxy_increase_liquidity(
+        ctx: Context<ProxyIncreaseLiquidity>,
+        liquidity: u128,
+        token_max_a: u64,
+        token_max_b: u64,
+    ) -> Result<()> {
+        increase_liquidity_handler(ctx, liquidity, token_max_a, token_max_b)
+    }
+
+    /// Admin only: removes `liquidity` from a vault position into the vault's token accounts,
+    /// receiving at least `token_min_a`/`token_min_b`.
+    pub fn proxy_decrease_liquidity(
+        ctx: Context<ProxyDecreaseLiquidity>,
+        liquidity: u128,
+        token_min_a: u64,
+        token_min_b: u64,
+    ) -> Result<()> {
+        decrease_liquidity_handler(ctx, liquidity, token_min_a, token_min_b)
+    }
+
+    /// Moves the vault's liquidity into the ranges its position strategies place around the
+    /// current price, split between the positions by weight. Runs once any position triggers.
+    /// `min_amount_a`/`min_amount_b` bound the tokens taken out of the current positions.
+    /// With `swap` set, the freed tokens are first swapped to the new ranges' ratio, which needs
+    /// the swap tick arrays and oracle accounts. Only the admin and keepers may swap, within the
+    /// vault's `max_swap_slippage_bps`.
+    ///
+    /// Remaining accounts: the vault's open positions (see [`position::load_positions`]), the
+    /// positions to open, one per slot (see [`position::load_new_positions`]), then the reward
+    /// accounts (see [`collect_rewards`]).
+    pub fn rebalance<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Rebalance<'info>>,
+        min_amount_a: u64,
+        min_amount_b: u64,
+        swap: Option<RebalanceSwap>,
     ) -> Result<()> {
-        // Check if liquidity is out of range before proceeding
-        let current_price = get_current_price(&ctx.accounts.whirlpool)?;
-        if current_price >= min_price && current_price <= max_price {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &ctx.accounts.vault;
+        require!(
+            vault
+                .rebalance_trigger
+                .is_cooled_down(vault.last_rebalance_ts, now),
+            CustomError::RebalanceCooldown
+        );
+
+        // Check if any position is out of range, or close enough to its edge, before proceeding
+        let is_triggered = vault
+            .positions
+            .iter()
+            .filter(|vault_position| vault_position.is_open())
+            .any(|vault_position| {
+                vault.rebalance_trigger.is_triggered(
+                    ctx.accounts.whirlpool.tick_current_index,
+                    vault_position.tick_lower_index,
+                    vault_position.tick_upper_index,
+                )
+            });
+        if !is_triggered {
             msg!("Liquidity is still in range, no need to rebalance.");
             return Ok(());
         }
-        rebalance_handler(
-            ctx,
-            current_price,
-            min_price,
-            max_price,
-            // tick_lower_index,
-            // tick_upper_index,
-        )?;
+        rebalance_handler(ctx, min_amount_a, min_amount_b, swap)?;
         Ok(())
     }
 }
 
-pub fn deposit_handler(
-    ctx: Context<Deposit>,
-    tick_lower_index: i32,
-    tick_upper_index: i32,
+pub fn rebalance_handler<'info>(
+    ctx: Context<'_, '_, 'info, 'info, Rebalance<'info>>,
+    // tick_lower_index: i32,
+    // tick_upper_index: i32,
+    min_amount_a: u64,
+    min_amount_b: u64,
+    swap: Option<RebalanceSwap>,
 ) -> Result<()> {
-    let cpi_program = ctx.accounts.whirlpool_program.to_account_info();
+    // the swap bounds come from the caller and are measured against the spot price, which it
+    // can move in the same transaction, so only the admin and keepers may swap
+    if let Some(params) = swap {
+        require!(
+            ctx.accounts.vault.is_keeper(&ctx.accounts.user.key()),
+            CustomError::Unauthorized
+        );
+        require!(
+            params.max_slippage_bps <= ctx.accounts.vault.max_swap_slippage_bps,
+            CustomError::SwapSlippageTooHigh
+        );
+    }
 
-    let cpi_accounts = whirlpool_cpi::cpi::accounts::OpenPosition {
-        funder: ctx.accounts.funder.to_account_info(),
-        owner: ctx.accounts.owner.to_account_info(),
-        position: ctx.accounts.position.to_account_info(),
-        position_mint: ctx.accounts.position_mint.to_account_info(),
-        position_token_account: ctx.accounts.position_token_account.to_account_info(),
+    let (positions, remaining_accounts) =
+        position::load_positions(&ctx.accounts.vault, ctx.remaining_accounts)?;
+    let (new_positions, reward_accounts) =
+        position::load_new_positions(&ctx.accounts.vault, remaining_accounts)?;
+
+    // unwind the current positions into the vault's token accounts
+    let cpi_program = ctx.accounts.whirlpool_program.to_account_info();
+    let pool = position::PoolAccounts {
+        whirlpool_program: cpi_program.clone(),
         whirlpool: ctx.accounts.whirlpool.to_account_info(),
-        token_program: ctx.accounts.token_program.to_account_info(),
-        system_program: ctx.accounts.system_program.to_account_info(),
-        rent: ctx.accounts.rent.to_account_info(),
-        associated_token_program: ctx.accounts.associated_token_program.to_account_info(),
+        vault: ctx.accounts.vault.to_account_info(),
+        token_mint_a: ctx.accounts.token_mint_a.to_account_info(),
+        token_mint_b: ctx.accounts.token_mint_b.to_account_info(),
+        token_owner_account_a: ctx.accounts.token_owner_account_a.to_account_info(),
+        token_owner_account_b: ctx.accounts.token_owner_account_b.to_account_info(),
+        token_vault_a: ctx.accounts.token_vault_a.to_account_info(),
+        token_vault_b: ctx.accounts.token_vault_b.to_account_info(),
+        token_program_a: ctx.accounts.token_program_a.to_account_info(),
+        token_program_b: ctx.accounts.token_program_b.to_account_info(),
+        memo_program: ctx.accounts.memo_program.to_account_info(),
     };
+    let (fees_a, fees_b) = unwind_positions(
+        UnwindAccounts {
+            pool: &pool,
+            whirlpool: &ctx.accounts.whirlpool,
+            vault: &ctx.accounts.vault,
+            receiver: ctx.accounts.receiver.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+            token_2022_program: ctx
+                .accounts
+                .token_2022_program
+                .as_ref()
+                .map(|program| program.to_account_info()),
+        },
+        positions,
+        reward_accounts,
+        min_amount_a,
+        min_amount_b,
+    )?;
 
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    // the caller is paid for the transaction out of the fees it harvested
+    let [fees_a, fees_b] = pay_keeper_fee(
+        &ctx.accounts.vault,
+        [
+            KeeperFeePayout {
+                token_program: ctx.accounts.token_program_a.to_account_info(),
+                mint: &ctx.accounts.token_mint_a,
+                from: ctx.accounts.token_owner_account_a.to_account_info(),
+                to: ctx.accounts.caller_token_account_a.to_account_info(),
+                fees: fees_a,
+            },
+            KeeperFeePayout {
+                token_program: ctx.accounts.token_program_b.to_account_info(),
+                mint: &ctx.accounts.token_mint_b,
+                from: ctx.accounts.token_owner_account_b.to_account_info(),
+                to: ctx.accounts.caller_token_account_b.to_account_info(),
+                fees: fees_b,
+            },
+        ],
+    )?;
 
-    // execute CPI
-    msg!("CPI: whirlpool open_position instruction");
-    whirlpool_cpi::cpi::open_position(
-        cpi_ctx,
-        whirlpool_cpi::state::OpenPositionBumps { position_bump: 0 }, // passed bump is no longer used
-        tick_lower_index,
-        tick_upper_index,
+    // charge the vault's fees on what was just harvested, everything is idle at this point
+    ctx.accounts.token_owner_account_a.reload()?;
+    ctx.accounts.token_owner_account_b.reload()?;
+    let sqrt_price = ctx.accounts.whirlpool.sqrt_price;
+    let harvested_value = (fees_b as u128)
+        .checked_add(nav::value_in_token_b(fees_a as u128, sqrt_price)?)
+        .ok_or_else(|| error!(CustomError::MathOverflow))?;
+    let total_value = (ctx.accounts.token_owner_account_b.amount as u128)
+        .checked_add(nav::value_in_token_b(
+            ctx.accounts.token_owner_account_a.amount as u128,
+            sqrt_price,
+        )?)
+        .ok_or_else(|| error!(CustomError::MathOverflow))?;
+    accrue_fees(
+        &mut ctx.accounts.vault,
+        ctx.accounts.vault_token_mint.to_account_info(),
+        ctx.accounts
+            .treasury_shares_account
+            .as_ref()
+            .map(|treasury| treasury.to_account_info()),
+        ctx.accounts.share_token_program.to_account_info(),
+        harvested_value,
+        total_value,
+    )?;
+    // the vault PDA owns the position NFTs and signs every whirlpool CPI
+    let vault_seeds = ctx.accounts.vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+
+    // open position
+    // let tick_lower_index = 0;
+    // let tick_upper_index = 10;
+    // Open new positions with the ranges the slots' strategies derive from the current price
+    let mut tick_ranges = Vec::with_capacity(new_positions.len());
+    let mut ranges = Vec::with_capacity(new_positions.len());
+    for vault_position in &ctx.accounts.vault.positions {
+        let (tick_lower_index, tick_upper_index) = vault_position
+            .strategy
+            .get_tick_range(&ctx.accounts.whirlpool)?;
+        tick_ranges.push((tick_lower_index, tick_upper_index));
+        ranges.push(nav::WeightedRange::new(
+            tick_lower_index,
+            tick_upper_index,
+            vault_position.weight_bps,
+        )?);
+    }
+
+    // Swap the freed tokens to the ratio the new ranges need
+    if let Some(params) = swap {
+        ctx.accounts.token_owner_account_a.reload()?;
+        ctx.accounts.token_owner_account_b.reload()?;
+        if let Some(swap) = nav::get_swap_to_ratio(
+            ctx.accounts.token_owner_account_a.amount,
+            ctx.accounts.token_owner_account_b.amount,
+            ctx.accounts.whirlpool.sqrt_price,
+            &ranges,
+        )? {
+            let (Some(tick_array0), Some(tick_array1), Some(tick_array2), Some(oracle)) = (
+                ctx.accounts.swap_tick_array_0.as_ref(),
+                ctx.accounts.swap_tick_array_1.as_ref(),
+                ctx.accounts.swap_tick_array_2.as_ref(),
+                ctx.accounts.oracle.as_ref(),
+            ) else {
+                return err!(CustomError::MissingSwapAccounts);
+            };
+            swap_to_ratio(
+                cpi_program.clone(),
+                whirlpool_cpi::cpi::accounts::SwapV2 {
+                    token_program_a: ctx.accounts.token_program_a.to_account_info(),
+                    token_program_b: ctx.accounts.token_program_b.to_account_info(),
+                    memo_program: ctx.accounts.memo_program.to_account_info(),
+                    token_authority: ctx.accounts.vault.to_account_info(),
+                    whirlpool: ctx.accounts.whirlpool.to_account_info(),
+                    token_mint_a: ctx.accounts.token_mint_a.to_account_info(),
+                    token_mint_b: ctx.accounts.token_mint_b.to_account_info(),
+                    token_owner_account_a: ctx.accounts.token_owner_account_a.to_account_info(),
+                    token_vault_a: ctx.accounts.token_vault_a.to_account_info(),
+                    token_owner_account_b: ctx.accounts.token_owner_account_b.to_account_info(),
+                    token_vault_b: ctx.accounts.token_vault_b.to_account_info(),
+                    tick_array_0: tick_array0.to_account_info(),
+                    tick_array_1: tick_array1.to_account_info(),
+                    tick_array_2: tick_array2.to_account_info(),
+                    oracle: oracle.to_account_info(),
+                },
+                &ctx.accounts.vault,
+                swap,
+                params.sqrt_price_limit,
+                params.max_slippage_bps,
+            )?;
+            ctx.accounts.whirlpool.reload()?;
+        }
+    }
+
+    let mut opened_positions = Vec::with_capacity(new_positions.len());
+    for (slot, (new_position, &(tick_lower_index, tick_upper_index))) in
+        new_positions.into_iter().zip(&tick_ranges).enumerate()
+    {
+        position::open_position(
+            &ctx.accounts.vault,
+            position::OpenPositionAccounts {
+                whirlpool_program: cpi_program.clone(),
+                funder: ctx.accounts.funder.to_account_info(),
+                position: new_position.position.clone(),
+                position_mint: new_position.position_mint.clone(),
+                position_token_account: new_position.position_token_account.clone(),
+                whirlpool: ctx.accounts.whirlpool.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                rent: ctx.accounts.rent.to_account_info(),
+                associated_token_program: ctx.accounts.associated_token_program.to_account_info(),
+                position_metadata_account: new_position.position_metadata_account,
+                metadata_program: ctx
+                    .accounts
+                    .metadata_program
+                    .as_ref()
+                    .map(|program| program.to_account_info()),
+                metadata_update_auth: ctx
+                    .accounts
+                    .metadata_update_auth
+                    .as_ref()
+                    .map(|account| account.to_account_info()),
+                token_2022_program: ctx
+                    .accounts
+                    .token_2022_program
+                    .as_ref()
+                    .map(|program| program.to_account_info()),
+            },
+            tick_lower_index,
+            tick_upper_index,
+        )?;
+        opened_positions.push(position::PositionAccounts {
+            slot,
+            position: Account::try_from(new_position.position)?,
+            position_mint: new_position.position_mint,
+            position_token_account: new_position.position_token_account,
+            tick_array_lower: new_position.tick_array_lower,
+            tick_array_upper: new_position.tick_array_upper,
+        });
+    }
+
+    // Re-deploy the largest liquidity the vault's balances can back in the new ranges
+    ctx.accounts.token_owner_account_a.reload()?;
+    ctx.accounts.token_owner_account_b.reload()?;
+    let deployed = add_liquidity_by_weight(
+        &pool,
+        signer_seeds,
+        &opened_positions,
+        &ranges,
+        ctx.accounts.whirlpool.sqrt_price,
+        ctx.accounts.token_owner_account_a.amount,
+        ctx.accounts.token_owner_account_b.amount,
     )?;
 
+    // track the new positions as the vault's active ones
+    let vault = &mut ctx.accounts.vault;
+    for (opened_position, &(tick_lower_index, tick_upper_index)) in
+        opened_positions.iter().zip(&tick_ranges)
+    {
+        vault.positions[opened_position.slot].open(
+            opened_position.position.key(),
+            opened_position.position_mint.key(),
+            tick_lower_index,
+            tick_upper_index,
+        );
+    }
+    vault.last_rebalance_ts = Clock::get()?.unix_timestamp;
+
+    emit!(Rebalanced {
+        vault: vault.key(),
+        positions: vault.positions.clone(),
+        liquidity: deployed.liquidity,
+        leftover_a: deployed.leftover_a,
+        leftover_b: deployed.leftover_b,
+    });
     Ok(())
 }
 
-pub fn withdraw_handler(ctx: Context<Withdraw>) -> Result<()> {
-    let cpi_program = ctx.accounts.whirlpool_program.to_account_info();
+/// Liquidity [`add_liquidity_by_weight`] added to each position and the balances it left idle.
+struct DeployedLiquidity {
+    liquidity: Vec<u128>,
+    leftover_a: u64,
+    leftover_b: u64,
+}
 
-    let cpi_accounts = whirlpool_cpi::cpi::accounts::ClosePosition {
-        position_authority: ctx.accounts.position_authority.to_account_info(),
-        receiver: ctx.accounts.receiver.to_account_info(),
-        position: ctx.accounts.position.to_account_info(),
-        position_mint: ctx.accounts.position_mint.to_account_info(),
-        position_token_account: ctx.accounts.position_token_account.to_account_info(),
-        token_program: ctx.accounts.token_program.to_account_info(),
+/// Adds the largest liquidity the vault's `balance_a`/`balance_b` can back to `positions`, once the
+/// transfer fees into the pool are taken, split between them along `ranges`, one per position.
+fn add_liquidity_by_weight<'info>(
+    pool: &position::PoolAccounts<'info>,
+    signer_seeds: &[&[&[u8]]],
+    positions: &[position::PositionAccounts<'info>],
+    ranges: &[nav::WeightedRange],
+    sqrt_price: u128,
+    balance_a: u64,
+    balance_b: u64,
+) -> Result<DeployedLiquidity> {
+    let balance_a = transfer_fee::get_amount_after_fee(&pool.token_mint_a, balance_a)?;
+    let balance_b = transfer_fee::get_amount_after_fee(&pool.token_mint_b, balance_b)?;
+    let amounts = nav::split_amounts(balance_a, balance_b, sqrt_price, ranges)?;
+
+    let mut deployed = DeployedLiquidity {
+        liquidity: Vec::with_capacity(positions.len()),
+        leftover_a: balance_a,
+        leftover_b: balance_b,
     };
+    for ((position, range), (position_balance_a, position_balance_b)) in
+        positions.iter().zip(ranges).zip(amounts)
+    {
+        let liquidity = liquidity_math::get_liquidity_from_amounts(
+            sqrt_price,
+            range.sqrt_price_lower_x64,
+            range.sqrt_price_upper_x64,
+            position_balance_a,
+            position_balance_b,
+        )?;
+        deployed.liquidity.push(liquidity);
+        if liquidity == 0 {
+            continue;
+        }
+        let (amount_a, amount_b) = liquidity_math::get_amounts_from_liquidity(
+            sqrt_price,
+            range.sqrt_price_lower_x64,
+            range.sqrt_price_upper_x64,
+            liquidity,
+            true,
+        )?;
+        pool.increase_liquidity(
+            position,
+            signer_seeds,
+            liquidity,
+            transfer_fee::get_amount_before_fee(&pool.token_mint_a, amount_a)?,
+            transfer_fee::get_amount_before_fee(&pool.token_mint_b, amount_b)?,
+        )?;
+        deployed.leftover_a = deployed.leftover_a.saturating_sub(amount_a);
+        deployed.leftover_b = deployed.leftover_b.saturating_sub(amount_b);
+    }
 
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    Ok(deployed)
+}
 
-    // execute CPI
-    msg!("CPI: whirlpool close_position instruction");
-    whirlpool_cpi::cpi::close_position(cpi_ctx)?;
+/// Swaps vault tokens through the whirlpool, signed by the vault. The output must come within
+/// `max_slippage_bps` of `swap.expected_out`, less the transfer fees on the way in and out, and
+/// the price may not cross `sqrt_price_limit`.
+fn swap_to_ratio<'info>(
+    whirlpool_program: AccountInfo<'info>,
+    accounts: whirlpool_cpi::cpi::accounts::SwapV2<'info>,
+    vault: &Account<'info, Vault>,
+    swap: nav::SwapToRatio,
+    sqrt_price_limit: u128,
+    max_slippage_bps: u16,
+) -> Result<()> {
+    require!(max_slippage_bps <= BPS_DENOMINATOR, CustomError::InvalidBps);
+    let (mint_in, mint_out) = if swap.a_to_b {
+        (&accounts.token_mint_a, &accounts.token_mint_b)
+    } else {
+        (&accounts.token_mint_b, &accounts.token_mint_a)
+    };
+    let amount_in = transfer_fee::get_amount_after_fee(mint_in, swap.amount_in)?;
+    let expected_out = full_math::mul_div_floor(
+        swap.expected_out as u128,
+        amount_in as u128,
+        swap.amount_in as u128,
+    )? as u64;
+    let expected_out = transfer_fee::get_amount_after_fee(mint_out, expected_out)?;
+    let other_amount_threshold = full_math::mul_div_floor(
+        expected_out as u128,
+        (BPS_DENOMINATOR - max_slippage_bps) as u128,
+        BPS_DENOMINATOR as u128,
+    )? as u64;
+
+    let vault_seeds = vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+    let cpi_ctx_swap = CpiContext::new_with_signer(whirlpool_program, accounts, signer_seeds);
 
-    Ok(())
+    // execute CPI
+    msg!("CPI: whirlpool swap_v2 instruction");
+    whirlpool_cpi::cpi::swap_v2(
+        cpi_ctx_swap,
+        swap.amount_in,
+        other_amount_threshold,
+        sqrt_price_limit,
+        true,
+        swap.a_to_b,
+        None,
+    )
 }
 
-pub fn rebalance_handler(
-    ctx: Context<Rebalance>,
-    // tick_lower_index: i32,
-    // tick_upper_index: i32,
-    current_price: u64,
-    min_price: u64,
-    max_price: u64,
+/// Unwinds the vault's positions and forgets them, shared by `emergency_exit` and
+/// `shutdown_vault`.
+fn unwind_vault<'info>(
+    ctx: Context<'_, '_, 'info, 'info, UnwindVault<'info>>,
+    min_amount_a: u64,
+    min_amount_b: u64,
 ) -> Result<()> {
-    // close position
-    let cpi_program = ctx.accounts.whirlpool_program.to_account_info();
+    let (positions, reward_accounts) =
+        position::load_positions(&ctx.accounts.vault, ctx.remaining_accounts)?;
+    unwind_positions(
+        UnwindAccounts {
+            pool: &position::PoolAccounts {
+                whirlpool_program: ctx.accounts.whirlpool_program.to_account_info(),
+                whirlpool: ctx.accounts.whirlpool.to_account_info(),
+                vault: ctx.accounts.vault.to_account_info(),
+                token_mint_a: ctx.accounts.token_mint_a.to_account_info(),
+                token_mint_b: ctx.accounts.token_mint_b.to_account_info(),
+                token_owner_account_a: ctx.accounts.vault_token_account_a.to_account_info(),
+                token_owner_account_b: ctx.accounts.vault_token_account_b.to_account_info(),
+                token_vault_a: ctx.accounts.token_vault_a.to_account_info(),
+                token_vault_b: ctx.accounts.token_vault_b.to_account_info(),
+                token_program_a: ctx.accounts.token_program_a.to_account_info(),
+                token_program_b: ctx.accounts.token_program_b.to_account_info(),
+                memo_program: ctx.accounts.memo_program.to_account_info(),
+            },
+            whirlpool: &ctx.accounts.whirlpool,
+            vault: &ctx.accounts.vault,
+            receiver: ctx.accounts.admin.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+            token_2022_program: ctx
+                .accounts
+                .token_2022_program
+                .as_ref()
+                .map(|program| program.to_account_info()),
+        },
+        positions,
+        reward_accounts,
+        min_amount_a,
+        min_amount_b,
+    )?;
 
-    let cpi_accounts_close_position = whirlpool_cpi::cpi::accounts::ClosePosition {
-        position_authority: ctx.accounts.position_authority.to_account_info(),
-        receiver: ctx.accounts.receiver.to_account_info(),
-        position: ctx.accounts.position.to_account_info(),
-        position_mint: ctx.accounts.position_mint.to_account_info(),
-        position_token_account: ctx.accounts.position_token_account.to_account_info(),
-        token_program: ctx.accounts.token_program.to_account_info(),
-    };
+    for vault_position in ctx.accounts.vault.positions.iter_mut() {
+        vault_position.close();
+    }
+    Ok(())
+}
 
-    let cpi_ctx_close_position = CpiContext::new(cpi_program.clone(), cpi_accounts_close_position);
+/// Accounts the vault's positions are unwound through, besides their own.
+pub struct UnwindAccounts<'a, 'info> {
+    pub pool: &'a position::PoolAccounts<'info>,
+    pub whirlpool: &'a Account<'info, Whirlpool>,
+    pub vault: &'a Account<'info, Vault>,
+    /// Receives the rent of the closed positions
+    pub receiver: AccountInfo<'info>,
+    /// Closes SPL Token positions
+    pub token_program: AccountInfo<'info>,
+    /// Closes Token-2022 positions
+    pub token_2022_program: Option<AccountInfo<'info>>,
+}
 
-    // execute CPI
-    msg!("CPI: whirlpool close_position instruction");
-    whirlpool_cpi::cpi::close_position(cpi_ctx_close_position)?;
+/// Empties and closes the vault's positions in turn: updates fees and rewards, removes all
+/// liquidity, collects fees and every reward slot, then closes the position. Whirlpool rejects
+/// `close_position` while the position still holds liquidity or owes fees or rewards.
+///
+/// `min_amount_a`/`min_amount_b` bound the tokens returned by removing the liquidity of all the
+/// positions. Returns the token A/B fees collected.
+pub fn unwind_positions<'info>(
+    accounts: UnwindAccounts<'_, 'info>,
+    positions: Vec<position::PositionAccounts<'info>>,
+    reward_accounts: &'info [AccountInfo<'info>],
+    min_amount_a: u64,
+    min_amount_b: u64,
+) -> Result<(u64, u64)> {
+    let vault_seeds = accounts.vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+    let pool = accounts.pool;
+
+    let (mut removed_a, mut removed_b) = (0u64, 0u64);
+    let (mut fees_a, mut fees_b) = (0u64, 0u64);
+    for mut position in positions {
+        // update fees and rewards so the owed amounts are current
+        position::update_fees_and_rewards(
+            pool.whirlpool_program.clone(),
+            pool.whirlpool.clone(),
+            &mut position,
+        )?;
 
-    // collect reward
-    let reward_index = 0;
-    let cpi_accounts_collect_reward = whirlpool_cpi::cpi::accounts::CollectReward {
-        whirlpool: ctx.accounts.whirlpool.to_account_info(),
-        position_authority: ctx.accounts.position_authority.to_account_info(),
-        position: ctx.accounts.position.to_account_info(),
-        position_token_account: ctx.accounts.position_token_account.to_account_info(),
-        reward_owner_account: ctx.accounts.reward_owner_account.to_account_info(),
-        reward_vault: ctx.accounts.reward_vault.to_account_info(),
-        token_program: ctx.accounts.token_program.to_account_info(),
-    };
+        // decrease liquidity, the minimums are checked against the total below
+        let liquidity = position.position.liquidity;
+        if liquidity > 0 {
+            let balance_a_before = token::accessor::amount(&pool.token_owner_account_a)?;
+            let balance_b_before = token::accessor::amount(&pool.token_owner_account_b)?;
+            pool.decrease_liquidity(&position, signer_seeds, liquidity, 0, 0)?;
+            removed_a += token::accessor::amount(&pool.token_owner_account_a)? - balance_a_before;
+            removed_b += token::accessor::amount(&pool.token_owner_account_b)? - balance_b_before;
+        }
 
-    let cpi_ctx_collect_reward = CpiContext::new(cpi_program.clone(), cpi_accounts_collect_reward);
+        // collect fees
+        let balance_a_before = token::accessor::amount(&pool.token_owner_account_a)?;
+        let balance_b_before = token::accessor::amount(&pool.token_owner_account_b)?;
+        pool.collect_fees(&position, signer_seeds)?;
+        fees_a += token::accessor::amount(&pool.token_owner_account_a)? - balance_a_before;
+        fees_b += token::accessor::amount(&pool.token_owner_account_b)? - balance_b_before;
+
+        // collect rewards
+        collect_rewards(
+            pool.whirlpool_program.clone(),
+            accounts.whirlpool,
+            accounts.vault,
+            position.position.to_account_info(),
+            position.position_token_account.clone(),
+            pool.memo_program.clone(),
+            reward_accounts,
+        )?;
 
-    // execute CPI
-    msg!("CPI: whirlpool collect_reward instruction");
-    whirlpool_cpi::cpi::collect_reward(cpi_ctx_collect_reward, reward_index)?;
+        // close position
+        position::close_position(
+            accounts.vault,
+            position::ClosePositionAccounts {
+                whirlpool_program: pool.whirlpool_program.clone(),
+                receiver: accounts.receiver.clone(),
+                position: position.position.to_account_info(),
+                position_mint: position.position_mint,
+                position_token_account: position.position_token_account,
+                token_program: accounts.token_program.clone(),
+                token_2022_program: accounts.token_2022_program.clone(),
+            },
+        )?;
+    }
+    require!(
+        removed_a >= min_amount_a && removed_b >= min_amount_b,
+        CustomError::SlippageExceeded
+    );
 
-    // collect fees
-    let cpi_accounts_collect_fees = whirlpool_cpi::cpi::accounts::CollectFees {
-        whirlpool: ctx.accounts.whirlpool.to_account_info(),
-        position_authority: ctx.accounts.position_authority.to_account_info(),
-        position: ctx.accounts.position.to_account_info(),
-        position_token_account: ctx.accounts.position_token_account.to_account_info(),
-        token_owner_account_a: ctx.accounts.token_owner_account_a.to_account_info(),
-        token_vault_a: ctx.accounts.token_vault_a.to_account_info(),
-        token_owner_account_b: ctx.accounts.token_owner_account_b.to_account_info(),
-        token_vault_b: ctx.accounts.token_vault_b.to_account_info(),
-        token_program: ctx.accounts.token_program.to_account_info(),
-    };
+    Ok((fees_a, fees_b))
+}
 
-    let cpi_ctx_collect_fees = CpiContext::new(cpi_program.clone(), cpi_accounts_collect_fees);
+/// Keeper fee leg of one pool token: fees harvested into the vault's `from` account, paid to the
+/// caller's `to` account.
+struct KeeperFeePayout<'a, 'info> {
+    token_program: AccountInfo<'info>,
+    mint: &'a InterfaceAccount<'info, Mint>,
+    from: AccountInfo<'info>,
+    to: AccountInfo<'info>,
+    fees: u64,
+}
 
-    // execute CPI
-    msg!("CPI: whirlpool collect_fees instruction");
-    whirlpool_cpi::cpi::collect_fees(cpi_ctx_collect_fees)?;
+/// Pays the caller `vault.keeper_fee_bps` of the fees just harvested, from the vault's token
+/// accounts. Returns the token A/B fees left to the vault.
+fn pay_keeper_fee<'info>(
+    vault: &Account<'info, Vault>,
+    payouts: [KeeperFeePayout<'_, 'info>; 2],
+) -> Result<[u64; 2]> {
+    let vault_seeds = vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+
+    let mut fees_left = [0; 2];
+    for (i, payout) in payouts.into_iter().enumerate() {
+        let amount = full_math::mul_div_floor(
+            payout.fees as u128,
+            vault.keeper_fee_bps as u128,
+            BPS_DENOMINATOR as u128,
+        )? as u64;
+        fees_left[i] = payout.fees - amount;
+        if amount == 0 {
+            continue;
+        }
+        transfer_tokens(
+            payout.token_program,
+            payout.from,
+            payout.to,
+            payout.mint,
+            vault.to_account_info(),
+            signer_seeds,
+            amount,
+        )?;
+    }
 
-    // open position
-    // let tick_lower_index = 0;
-    // let tick_upper_index = 10;
-    // Open new position with correct tick intervals
-    let tick_lower_index = determine_tick_lower(current_price, min_price)?;
-    let tick_upper_index = determine_tick_upper(current_price, max_price)?;
-    let cpi_accounts_open_position = whirlpool_cpi::cpi::accounts::OpenPosition {
-        funder: ctx.accounts.funder.to_account_info(),
-        owner: ctx.accounts.owner.to_account_info(),
-        position: ctx.accounts.position.to_account_info(),
-        position_mint: ctx.accounts.position_mint.to_account_info(),
-        position_token_account: ctx.accounts.position_token_account.to_account_info(),
-        whirlpool: ctx.accounts.whirlpool.to_account_info(),
-        token_program: ctx.accounts.token_program.to_account_info(),
-        system_program: ctx.accounts.system_program.to_account_info(),
-        rent: ctx.accounts.rent.to_account_info(),
-        associated_token_program: ctx.accounts.associated_token_program.to_account_info(),
+    Ok(fees_left)
+}
+
+/// Moves `amount` of `mint` with `transfer_checked`, which Token-2022 requires for mints with
+/// extensions such as transfer fees. `signer_seeds` is empty when `authority` signs itself.
+fn transfer_tokens<'info>(
+    token_program: AccountInfo<'info>,
+    from: AccountInfo<'info>,
+    to: AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    authority: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    let cpi_accounts = token_interface::TransferChecked {
+        from,
+        mint: mint.to_account_info(),
+        to,
+        authority,
     };
+    let cpi_context = CpiContext::new_with_signer(token_program, cpi_accounts, signer_seeds);
+    token_interface::transfer_checked(cpi_context, amount, mint.decimals)
+}
 
-    let cpi_ctx_open_position = CpiContext::new(cpi_program.clone(), cpi_accounts_open_position);
+/// Mints the management fee accrued since the last accrual, and the performance fee on
+/// `harvested_value`, to the treasury as shares priced against `total_value` (both in token B).
+fn accrue_fees<'info>(
+    vault: &mut Account<'info, Vault>,
+    share_mint: AccountInfo<'info>,
+    treasury: Option<AccountInfo<'info>>,
+    share_token_program: AccountInfo<'info>,
+    harvested_value: u128,
+    total_value: u128,
+) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let fee_config = vault.fee_config;
+    let elapsed = now - vault.last_fee_accrual_ts;
+    vault.last_fee_accrual_ts = now;
+    if vault.total_shares == 0 {
+        return Ok(());
+    }
 
-    // execute CPI
-    msg!("CPI: whirlpool open_position instruction");
-    whirlpool_cpi::cpi::open_position(
-        cpi_ctx_open_position,
-        whirlpool_cpi::state::OpenPositionBumps { position_bump: 0 }, // passed bump is no longer used
-        tick_lower_index,
-        tick_upper_index,
+    let management_fee_shares = share_math::get_management_fee_shares(
+        vault.total_shares,
+        fee_config.management_fee_bps,
+        elapsed,
+    )?;
+    let performance_fee_value = full_math::mul_div_floor(
+        harvested_value,
+        fee_config.performance_fee_bps as u128,
+        BPS_DENOMINATOR as u128,
+    )?;
+    // priced so the treasury owns `performance_fee_value` of the vault once minted
+    let performance_fee_shares = share_math::get_shares_for_value(
+        performance_fee_value,
+        vault.total_shares,
+        total_value.saturating_sub(performance_fee_value),
     )?;
+    let fee_shares = management_fee_shares
+        .checked_add(performance_fee_shares)
+        .ok_or_else(|| error!(CustomError::MathOverflow))?;
+    if fee_shares == 0 {
+        return Ok(());
+    }
+
+    let treasury = treasury.ok_or_else(|| error!(CustomError::MissingTreasury))?;
+    let vault_seeds = vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+    let cpi_accounts = token_interface::MintTo {
+        mint: share_mint,
+        to: treasury,
+        authority: vault.to_account_info(),
+    };
+    let cpi_context = CpiContext::new_with_signer(share_token_program, cpi_accounts, signer_seeds);
+    token_interface::mint_to(cpi_context, fee_shares)?;
+
+    vault.total_shares = vault
+        .total_shares
+        .checked_add(fee_shares)
+        .ok_or_else(|| error!(CustomError::MathOverflow))?;
     Ok(())
 }
 
-fn get_current_price(_whirlpool: &Account<Whirlpool>) -> Result<u64> {
-    // This function should get the current price from the given Whirlpool account.
-    // Placeholder implementation for demo purposes.
-    Ok(100) // Replace with actual price fetch logic
-}
+/// Collects every initialized reward slot of `position` into vault-owned token accounts.
+///
+/// `reward_accounts` holds `(reward_owner_account, reward_vault, reward_mint,
+/// reward_token_program)` for each initialized slot of `whirlpool.reward_infos`, in slot order.
+/// Uninitialized slots take no accounts.
+pub fn collect_rewards<'info>(
+    whirlpool_program: AccountInfo<'info>,
+    whirlpool: &Account<'info, Whirlpool>,
+    vault: &Account<'info, Vault>,
+    position: AccountInfo<'info>,
+    position_token_account: AccountInfo<'info>,
+    memo_program: AccountInfo<'info>,
+    reward_accounts: &'info [AccountInfo<'info>],
+) -> Result<()> {
+    let vault_seeds = vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+
+    let initialized_rewards = whirlpool
+        .reward_infos
+        .iter()
+        .enumerate()
+        .filter(|(_, reward_info)| reward_info.mint != Pubkey::default());
+    let mut reward_accounts = reward_accounts.chunks_exact(4);
+    for (reward_index, reward_info) in initialized_rewards {
+        let [reward_owner_account, reward_vault, reward_mint, reward_token_program] =
+            reward_accounts
+                .next()
+                .ok_or_else(|| error!(CustomError::InvalidRewardAccounts))?
+        else {
+            return err!(CustomError::InvalidRewardAccounts);
+        };
+        let owner_account = InterfaceAccount::<TokenAccount>::try_from(reward_owner_account)?;
+        require!(
+            owner_account.mint == reward_info.mint && owner_account.owner == vault.key(),
+            CustomError::InvalidRewardAccounts
+        );
+        // rewards paid in a pool token join the idle balances the vault NAV counts
+        if reward_info.mint == whirlpool.token_mint_a {
+            require_keys_eq!(
+                owner_account.key(),
+                vault.token_account_a,
+                CustomError::InvalidRewardAccounts
+            );
+        } else if reward_info.mint == whirlpool.token_mint_b {
+            require_keys_eq!(
+                owner_account.key(),
+                vault.token_account_b,
+                CustomError::InvalidRewardAccounts
+            );
+        }
+        require_keys_eq!(
+            reward_vault.key(),
+            reward_info.vault,
+            CustomError::InvalidRewardAccounts
+        );
+        require_keys_eq!(
+            reward_mint.key(),
+            reward_info.mint,
+            CustomError::InvalidRewardAccounts
+        );
+        require_keys_eq!(
+            reward_token_program.key(),
+            *reward_mint.owner,
+            CustomError::InvalidRewardAccounts
+        );
+
+        let cpi_accounts_collect_reward = whirlpool_cpi::cpi::accounts::CollectRewardV2 {
+            whirlpool: whirlpool.to_account_info(),
+            position_authority: vault.to_account_info(),
+            position: position.clone(),
+            position_token_account: position_token_account.clone(),
+            reward_owner_account: reward_owner_account.clone(),
+            reward_mint: reward_mint.clone(),
+            reward_vault: reward_vault.clone(),
+            reward_token_program: reward_token_program.clone(),
+            memo_program: memo_program.clone(),
+        };
+        let cpi_ctx_collect_reward = CpiContext::new_with_signer(
+            whirlpool_program.clone(),
+            cpi_accounts_collect_reward,
+            signer_seeds,
+        );
+
+        // execute CPI
+        msg!("CPI: whirlpool collect_reward_v2 instruction");
+        whirlpool_cpi::cpi::collect_reward_v2(cpi_ctx_collect_reward, reward_index as u8, None)?;
+    }
+    require!(
+        reward_accounts.next().is_none() && reward_accounts.remainder().is_empty(),
+        CustomError::InvalidRewardAccounts
+    );
 
-fn determine_tick_lower(current_price: u64, min_price: u64) -> Result<i32> {
-    // Placeholder function to determine the lower tick index.
-    Ok((current_price - min_price) as i32)
+    Ok(())
 }
 
-fn determine_tick_upper(current_price: u64, max_price: u64) -> Result<i32> {
-    // Placeholder function to determine the upper tick index.
-    Ok((max_price - current_price) as i32)
+#[derive(Accounts)]
+pub struct InitializeRegistry<'info> {
+    #[account(
+        init,
+        seeds = [VaultRegistry::SEED, authority.key().as_ref()],
+        bump,
+        payer = authority,
+        space = 8 + VaultRegistry::INIT_SPACE
+    )]
+    pub registry: Account<'info, VaultRegistry>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-// #[instruction(instruction_data: String)]
+#[instruction(
+    strategy_id: u16,
+    layers: Vec<PositionLayer>,
+    rebalance_trigger: RebalanceTrigger,
+    min_deposit_value: u64,
+    share_decimals: u8,
+    position_kind: PositionKind
+)]
 pub struct InitializeVault<'info> {
     #[account(
         init,
         seeds = [
-            b"vault",
+            Vault::SEED,
+            whirlpool.key().as_ref(),
+            &strategy_id.to_le_bytes(),
             user.key().as_ref(),
-            // instruction_data.as_ref()
         ],
         bump,
         payer = user,
-        space = 8 + 64
+        space = 8 + Vault::INIT_SPACE
     )]
     pub vault: Account<'info, Vault>,
     #[account(mut)]
     pub user: Signer<'info>,
+    /// Created beforehand by `initialize_registry`
+    #[account(
+        mut,
+        seeds = [VaultRegistry::SEED, user.key().as_ref()],
+        bump = registry.bump,
+        constraint = registry.authority == user.key() @ CustomError::Unauthorized
+    )]
+    pub registry: Account<'info, VaultRegistry>,
     pub system_program: Program<'info, System>,
-    pub lp_token_account: Account<'info, TokenAccount>,
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+
+    /// The vault's token A/B ATAs, created beforehand, which hold its idle balances
+    #[account(
+        associated_token::mint = token_mint_a,
+        associated_token::authority = vault,
+        associated_token::token_program = token_program_a,
+    )]
+    pub vault_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        associated_token::mint = token_mint_b,
+        associated_token::authority = vault,
+        associated_token::token_program = token_program_b,
+    )]
+    pub vault_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(address = whirlpool.token_mint_a, mint::token_program = token_program_a)]
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = whirlpool.token_mint_b, mint::token_program = token_program_b)]
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+
+    #[account(
+        init,
+        seeds = [Vault::SHARE_MINT_SEED, vault.key().as_ref()],
+        bump,
+        payer = user,
+        mint::decimals = share_decimals,
+        mint::authority = vault,
+        mint::token_program = share_token_program,
+    )]
+    pub vault_token_mint: InterfaceAccount<'info, Mint>,
+    /// SPL Token or Token-2022, decides which program the share mint lives under
+    pub share_token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct Deposit<'info> {
-    pub whirlpool_program: Program<'info, WhirlpoolProgram>,
-
+pub struct InitializeShareMetadata<'info> {
+    #[account(has_one = creator)]
+    pub vault: Account<'info, Vault>,
     #[account(mut)]
-    pub funder: Signer<'info>,
+    pub creator: Signer<'info>,
 
-    /// CHECK: safe (the owner of position_token_account)
-    pub owner: UncheckedAccount<'info>,
+    #[account(address = vault.share_mint)]
+    pub vault_token_mint: InterfaceAccount<'info, Mint>,
 
-    /// CHECK: init by whirlpool
-    #[account(mut)]
-    pub position: UncheckedAccount<'info>,
+    /// CHECK: initialized by the token metadata program
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            metadata_program.key().as_ref(),
+            vault_token_mint.key().as_ref(),
+        ],
+        bump,
+        seeds::program = metadata_program.key()
+    )]
+    pub share_metadata: UncheckedAccount<'info>,
 
-    /// CHECK: init by whirlpool
-    #[account(mut)]
-    pub position_mint: Signer<'info>,
+    pub metadata_program: Program<'info, Metadata>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
 
-    /// CHECK: init by whirlpool
-    #[account(mut)]
-    pub position_token_account: UncheckedAccount<'info>,
+#[derive(Accounts)]
+pub struct UpdateVault<'info> {
+    #[account(mut, has_one = admin)]
+    pub vault: Account<'info, Vault>,
+    pub admin: Signer<'info>,
+}
 
-    pub whirlpool: Box<Account<'info, Whirlpool>>,
+#[derive(Accounts)]
+#[instruction(fee_config: FeeConfig)]
+pub struct ProposeFeeConfig<'info> {
+    #[account(mut, has_one = admin)]
+    pub vault: Account<'info, Vault>,
+    pub admin: Signer<'info>,
+    #[account(address = fee_config.treasury, token::mint = vault.share_mint)]
+    pub treasury_shares_account: Box<InterfaceAccount<'info, TokenAccount>>,
+}
 
-    #[account(address = token::ID)]
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+#[derive(Accounts)]
+pub struct ApplyFeeConfig<'info> {
+    #[account(mut, has_one = admin)]
+    pub vault: Account<'info, Vault>,
+    pub admin: Signer<'info>,
+
+    #[account(mut, address = vault.share_mint, mint::token_program = share_token_program)]
+    pub vault_token_mint: InterfaceAccount<'info, Mint>,
+    pub share_token_program: Interface<'info, TokenInterface>,
+    /// Only needed when fees are due under the current fees
+    #[account(mut, address = vault.fee_config.treasury, token::mint = vault.share_mint)]
+    pub treasury_shares_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+}
 
-    #[account(mut)]
+#[derive(Accounts)]
+pub struct DepositTokens<'info> {
+    #[account(mut, has_one = whirlpool)]
     pub vault: Account<'info, Vault>,
+    pub user: Signer<'info>,
+
+    pub whirlpool_program: Program<'info, WhirlpoolProgram>,
+
     #[account(mut)]
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+
+    #[account(mut, constraint = user_token_account_a.mint == whirlpool.token_mint_a)]
+    pub user_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, constraint = user_token_account_b.mint == whirlpool.token_mint_b)]
+    pub user_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = vault.token_account_a)]
+    pub vault_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = vault.token_account_b)]
+    pub vault_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = whirlpool.token_vault_a)]
+    pub token_vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = whirlpool.token_vault_b)]
+    pub token_vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = vault.share_mint, mint::token_program = share_token_program)]
+    pub vault_token_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = user_shares_account.mint == vault.share_mint)]
+    pub user_shares_account: InterfaceAccount<'info, TokenAccount>,
+    pub share_token_program: Interface<'info, TokenInterface>,
+    /// Only needed when fees are due
+    #[account(mut, address = vault.fee_config.treasury, token::mint = vault.share_mint)]
+    pub treasury_shares_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(address = whirlpool.token_mint_a, mint::token_program = token_program_a)]
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = whirlpool.token_mint_b, mint::token_program = token_program_b)]
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub memo_program: Program<'info, Memo>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTokens<'info> {
+    #[account(mut, has_one = whirlpool)]
+    pub vault: Account<'info, Vault>,
     pub user: Signer<'info>,
+
+    pub whirlpool_program: Program<'info, WhirlpoolProgram>,
+
     #[account(mut)]
-    pub user_lp_token_account: Account<'info, TokenAccount>,
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+
+    #[account(mut, constraint = user_token_account_a.mint == whirlpool.token_mint_a)]
+    pub user_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, constraint = user_token_account_b.mint == whirlpool.token_mint_b)]
+    pub user_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = vault.token_account_a)]
+    pub vault_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = vault.token_account_b)]
+    pub vault_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = whirlpool.token_vault_a)]
+    pub token_vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = whirlpool.token_vault_b)]
+    pub token_vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = vault.share_mint, mint::token_program = share_token_program)]
+    pub vault_token_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = user_shares_account.mint == vault.share_mint)]
+    pub user_shares_account: InterfaceAccount<'info, TokenAccount>,
+    pub share_token_program: Interface<'info, TokenInterface>,
+    /// Only needed when fees are due
+    #[account(mut, address = vault.fee_config.treasury, token::mint = vault.share_mint)]
+    pub treasury_shares_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(address = whirlpool.token_mint_a, mint::token_program = token_program_a)]
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = whirlpool.token_mint_b, mint::token_program = token_program_b)]
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub memo_program: Program<'info, Memo>,
+}
+
+#[derive(Accounts)]
+pub struct Compound<'info> {
+    #[account(mut, has_one = whirlpool,
+      constraint = vault.is_operator(&caller.key()) @ CustomError::Unauthorized)]
+    pub vault: Account<'info, Vault>,
+    pub caller: Signer<'info>,
+    #[account(mut, constraint = caller_token_account_a.mint == whirlpool.token_mint_a)]
+    pub caller_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, constraint = caller_token_account_b.mint == whirlpool.token_mint_b)]
+    pub caller_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = vault.share_mint, mint::token_program = share_token_program)]
+    pub vault_token_mint: InterfaceAccount<'info, Mint>,
+    pub share_token_program: Interface<'info, TokenInterface>,
+    /// Only needed when fees are due
+    #[account(mut, address = vault.fee_config.treasury, token::mint = vault.share_mint)]
+    pub treasury_shares_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    pub whirlpool_program: Program<'info, WhirlpoolProgram>,
+
     #[account(mut)]
-    pub vault_lp_token_account: Account<'info, TokenAccount>,
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+
+    #[account(mut, address = vault.token_account_a)]
+    pub vault_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = vault.token_account_b)]
+    pub vault_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = whirlpool.token_vault_a)]
+    pub token_vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = whirlpool.token_vault_b)]
+    pub token_vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
+    /// swap, only needed when a keeper compounds with a swap
+    /// CHECK: checked by whirlpool
     #[account(mut)]
-    pub vault_token_mint: Account<'info, Mint>,
+    pub swap_tick_array_0: Option<UncheckedAccount<'info>>,
+    /// CHECK: checked by whirlpool
     #[account(mut)]
-    pub user_shares_account: Account<'info, TokenAccount>,
+    pub swap_tick_array_1: Option<UncheckedAccount<'info>>,
+    /// CHECK: checked by whirlpool
+    #[account(mut)]
+    pub swap_tick_array_2: Option<UncheckedAccount<'info>>,
+    /// CHECK: checked by whirlpool
+    pub oracle: Option<UncheckedAccount<'info>>,
+
+    #[account(address = whirlpool.token_mint_a, mint::token_program = token_program_a)]
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = whirlpool.token_mint_b, mint::token_program = token_program_b)]
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub memo_program: Program<'info, Memo>,
 }
 
 #[derive(Accounts)]
-pub struct Withdraw<'info> {
-    pub whirlpool_program: Program<'info, WhirlpoolProgram>,
+pub struct Harvest<'info> {
+    #[account(has_one = whirlpool)]
+    pub vault: Account<'info, Vault>,
 
-    pub position_authority: Signer<'info>,
+    pub whirlpool_program: Program<'info, WhirlpoolProgram>,
 
-    /// CHECK: safe (the account to receive the remaining balance of the closed account)
     #[account(mut)]
-    pub receiver: UncheckedAccount<'info>,
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+
+    pub memo_program: Program<'info, Memo>,
+}
+
+#[derive(Accounts)]
+pub struct SweepReward<'info> {
+    #[account(has_one = whirlpool,
+      constraint = vault.is_keeper(&caller.key()) @ CustomError::Unauthorized)]
+    pub vault: Account<'info, Vault>,
+    pub caller: Signer<'info>,
+
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
 
+    #[account(
+        mint::token_program = reward_token_program,
+        constraint = reward_mint.key() != whirlpool.token_mint_a
+            && reward_mint.key() != whirlpool.token_mint_b @ CustomError::PoolTokenNotSweepable
+    )]
+    pub reward_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub reward_token_program: Interface<'info, TokenInterface>,
+
+    #[account(mut, token::mint = reward_mint, token::authority = vault)]
+    pub vault_reward_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(address = vault.fee_config.treasury, token::mint = vault.share_mint)]
+    pub treasury_shares_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        token::mint = reward_mint,
+        token::authority = treasury_shares_account.owner
+    )]
+    pub treasury_reward_account: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct UnwindVault<'info> {
+    #[account(mut, has_one = admin, has_one = whirlpool)]
+    pub vault: Account<'info, Vault>,
+    /// Receives the rent of the closed positions
     #[account(mut)]
-    pub position: Account<'info, Position>,
+    pub admin: Signer<'info>,
 
-    #[account(mut, address = position.position_mint)]
-    pub position_mint: Account<'info, Mint>,
+    pub whirlpool_program: Program<'info, WhirlpoolProgram>,
 
-    #[account(mut,
-      constraint = position_token_account.amount == 1,
-      constraint = position_token_account.mint == position.position_mint)]
-    pub position_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+
+    #[account(mut, address = vault.token_account_a)]
+    pub vault_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = vault.token_account_b)]
+    pub vault_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
 
+    #[account(mut, address = whirlpool.token_vault_a)]
+    pub token_vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = whirlpool.token_vault_b)]
+    pub token_vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = whirlpool.token_mint_a, mint::token_program = token_program_a)]
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = whirlpool.token_mint_b, mint::token_program = token_program_b)]
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub memo_program: Program<'info, Memo>,
+    /// Closes the position NFTs
     #[account(address = token::ID)]
     pub token_program: Program<'info, Token>,
+    /// Token-2022 positions only
+    pub token_2022_program: Option<Program<'info, Token2022>>,
+}
 
-    #[account(mut)]
+#[derive(Accounts)]
+pub struct WithdrawIdle<'info> {
+    #[account(mut, has_one = whirlpool)]
     pub vault: Account<'info, Vault>,
-    #[account(mut)]
     pub user: Signer<'info>,
-    #[account(mut)]
-    pub user_lp_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub vault_lp_token_account: Account<'info, TokenAccount>,
 
-    #[account(mut)]
-    pub vault_token_mint: Account<'info, Mint>,
-    #[account(mut)]
-    pub user_shares_account: Account<'info, TokenAccount>,
+    pub whirlpool: Box<Account<'info, Whirlpool>>,
+
+    #[account(mut, constraint = user_token_account_a.mint == whirlpool.token_mint_a)]
+    pub user_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, constraint = user_token_account_b.mint == whirlpool.token_mint_b)]
+    pub user_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = vault.token_account_a)]
+    pub vault_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, address = vault.token_account_b)]
+    pub vault_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = vault.share_mint, mint::token_program = share_token_program)]
+    pub vault_token_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = user_shares_account.mint == vault.share_mint)]
+    pub user_shares_account: InterfaceAccount<'info, TokenAccount>,
+    pub share_token_program: Interface<'info, TokenInterface>,
+
+    #[account(address = whirlpool.token_mint_a, mint::token_program = token_program_a)]
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = whirlpool.token_mint_b, mint::token_program = token_program_b)]
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-#[instruction(reward_index: u8)]
 pub struct Rebalance<'info> {
-    #[account(mut)]
+    #[account(mut, has_one = whirlpool,
+      constraint = vault.is_operator(&user.key()) @ CustomError::Unauthorized)]
     pub vault: Account<'info, Vault>,
     #[account(mut)]
     pub user: Signer<'info>,
-    #[account(mut)]
-    pub lp_token_account: Account<'info, TokenAccount>,
+    #[account(mut, constraint = caller_token_account_a.mint == whirlpool.token_mint_a)]
+    pub caller_token_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut, constraint = caller_token_account_b.mint == whirlpool.token_mint_b)]
+    pub caller_token_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = vault.share_mint, mint::token_program = share_token_program)]
+    pub vault_token_mint: InterfaceAccount<'info, Mint>,
+    pub share_token_program: Interface<'info, TokenInterface>,
+    /// Only needed when fees are due
+    #[account(mut, address = vault.fee_config.treasury, token::mint = vault.share_mint)]
+    pub treasury_shares_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
     pub whirlpool_program: Program<'info, WhirlpoolProgram>,
 
-    /// additional
-    #[account(mut)]
-    pub user_lp_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub vault_lp_token_account: Account<'info, TokenAccount>,
     // pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 
     /// close position
     // pub whirlpool_program: Program<'info, WhirlpoolProgram>,
-    pub position_authority: Signer<'info>,
+    // pub position_authority: Signer<'info>,
 
     /// CHECK: safe (the account to receive the remaining balance of the closed account)
     #[account(mut)]
     pub receiver: UncheckedAccount<'info>,
 
-    // #[account(mut)]
-    // pub position: Account<'info, Position>,
-    #[account(mut, address = position.position_mint)]
-    pub position_mint: Account<'info, Mint>,
-
-    #[account(mut,
-      constraint = position_token_account.amount == 1,
-      constraint = position_token_account.mint == position.position_mint)]
-    pub position_token_account: Box<Account<'info, TokenAccount>>,
-
+    /// Opens and closes the position NFTs
     #[account(address = token::ID)]
     pub token_program: Program<'info, Token>,
+    #[account(address = whirlpool.token_mint_a, mint::token_program = token_program_a)]
+    pub token_mint_a: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = whirlpool.token_mint_b, mint::token_program = token_program_b)]
+    pub token_mint_b: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub memo_program: Program<'info, Memo>,
 
     /// collect fees
+    #[account(mut)]
     pub whirlpool: Box<Account<'info, Whirlpool>>,
 
     // pub position_authority: Signer<'info>,
-    #[account(mut, has_one = whirlpool)]
-    pub position: Box<Account<'info, Position>>,
     //     #[account(
     //       constraint = position_token_account.mint == position.position_mint,
     //       constraint = position_token_account.amount == 1
     //   )]
     //     pub position_token_account: Box<Account<'info, TokenAccount>>,
-    #[account(mut, constraint = token_owner_account_a.mint == whirlpool.token_mint_a)]
-    pub token_owner_account_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = vault.token_account_a)]
+    pub token_owner_account_a: Box<InterfaceAccount<'info, TokenAccount>>,
     #[account(mut, address = whirlpool.token_vault_a)]
-    pub token_vault_a: Box<Account<'info, TokenAccount>>,
+    pub token_vault_a: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(mut, constraint = token_owner_account_b.mint == whirlpool.token_mint_b)]
-    pub token_owner_account_b: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = vault.token_account_b)]
+    pub token_owner_account_b: Box<InterfaceAccount<'info, TokenAccount>>,
     #[account(mut, address = whirlpool.token_vault_b)]
-    pub token_vault_b: Box<Account<'info, TokenAccount>>,
+    pub token_vault_b: Box<InterfaceAccount<'info, TokenAccount>>,
     // #[account(address = token::ID)]
     // pub token_program: Program<'info, Token>,
-    /// collect reward
-    #[account(mut,
-        constraint = reward_owner_account.mint == whirlpool.reward_infos[reward_index as usize].mint
-    )]
-    pub reward_owner_account: Box<Account<'info, TokenAccount>>,
-
-    #[account(mut, address = whirlpool.reward_infos[reward_index as usize].vault)]
-    pub reward_vault: Box<Account<'info, TokenAccount>>,
-
-    /// open position
+    /// open positions
     #[account(mut)]
     pub funder: Signer<'info>,
 
-    /// CHECK: safe (the owner of position_token_account)
-    pub owner: UncheckedAccount<'info>,
+    /// `WithMetadata` vaults only
+    pub metadata_program: Option<Program<'info, Metadata>>,
+    /// CHECK: checked by whirlpool
+    pub metadata_update_auth: Option<UncheckedAccount<'info>>,
+    /// Token-2022 positions only
+    pub token_2022_program: Option<Program<'info, Token2022>>,
+
+    /// swap, only needed when rebalancing with a swap
+    /// CHECK: checked by whirlpool
+    #[account(mut)]
+    pub swap_tick_array_0: Option<UncheckedAccount<'info>>,
+    /// CHECK: checked by whirlpool
+    #[account(mut)]
+    pub swap_tick_array_1: Option<UncheckedAccount<'info>>,
+    /// CHECK: checked by whirlpool
+    #[account(mut)]
+    pub swap_tick_array_2: Option<UncheckedAccount<'info>>,
+    /// CHECK: checked by whirlpool
+    pub oracle: Option<UncheckedAccount<'info>>,
 
     // /// CHECK: init by whirlpool
     // #[account(mut)]
@@ -516,16 +2389,143 @@ pub struct Rebalance<'info> {
     // pub vault: Account<'info, Vault>,
     // #[account(mut)]
     // pub user: Signer<'info>,
-    // #[account(mut)]
-    // pub user_lp_token_account: Account<'info, TokenAccount>,
-    // #[account(mut)]
-    // pub vault_lp_token_account: Account<'info, TokenAccount>,
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct Vault {
     pub bump: u8,
-    pub lp_token_account: Pubkey,
-    pub total_lp_tokens: u64,
+    /// Wallet the vault PDA is derived from
+    pub creator: Pubkey,
+    /// Configures the vault and may step in on its position, starts as the creator
+    pub admin: Pubkey,
+    /// Allowed to rebalance and compound besides the admin
+    #[max_len(MAX_VAULT_KEEPERS)]
+    pub keepers: Vec<Pubkey>,
+    /// Lets anyone rebalance and compound
+    pub permissionless: bool,
+    /// Share of the fees harvested by rebalance and compound paid to the caller
+    pub keeper_fee_bps: u16,
+    /// Worst swap price, versus the pool price, rebalance and compound accept
+    pub max_swap_slippage_bps: u16,
+    pub fee_config: FeeConfig,
+    /// Fee change waiting for its timelock, applicable from `pending_fee_config_ts`, 0 if none
+    pub pending_fee_config: FeeConfig,
+    pub pending_fee_config_ts: i64,
+    /// Unix timestamp the management fee was last charged up to
+    pub last_fee_accrual_ts: i64,
+    /// Little-endian `strategy_id` the vault PDA is derived from, see [`Vault::strategy_id`]
+    pub strategy_id_seed: [u8; 2],
+    pub rebalance_trigger: RebalanceTrigger,
+    /// Unix timestamp of the last rebalance
+    pub last_rebalance_ts: i64,
     pub total_shares: u64,
+    pub whirlpool: Pubkey,
+    /// Vault ATAs of the pool tokens, the only accounts its idle balances are read from and paid
+    /// out of
+    pub token_account_a: Pubkey,
+    pub token_account_b: Pubkey,
+    /// Position ladder, each slot with its own range policy and weight
+    #[max_len(MAX_VAULT_POSITIONS)]
+    pub positions: Vec<VaultPosition>,
+    /// Open/close instruction variant used for the vault's positions
+    pub position_kind: PositionKind,
+    /// Smallest deposit accepted, valued in token B
+    pub min_deposit_value: u64,
+    /// Vault share mint, a PDA of the vault with the vault as mint authority
+    pub share_mint: Pubkey,
+    /// Set by `shutdown_vault`, no deposits are accepted afterwards
+    pub is_shut_down: bool,
+}
+
+impl Vault {
+    pub const SEED: &'static [u8] = b"vault";
+    pub const SHARE_MINT_SEED: &'static [u8] = b"share_mint";
+    /// Worst swap price, versus the pool price, a new vault accepts
+    pub const DEFAULT_MAX_SWAP_SLIPPAGE_BPS: u16 = 100;
+    /// Highest keeper fee the admin can set
+    pub const MAX_KEEPER_FEE_BPS: u16 = 1_000;
+
+    /// Whether `key` may run the vault's routine operations: rebalance and compound.
+    pub fn is_operator(&self, key: &Pubkey) -> bool {
+        self.permissionless || self.is_keeper(key)
+    }
+
+    /// Whether `key` is the admin or one of the keepers, trusted to swap the vault's tokens.
+    pub fn is_keeper(&self, key: &Pubkey) -> bool {
+        *key == self.admin || self.keepers.contains(key)
+    }
+
+    /// Whether any slot has a position open.
+    pub fn has_open_position(&self) -> bool {
+        self.positions.iter().any(VaultPosition::is_open)
+    }
+
+    /// Slot of `position` among the vault's open positions.
+    pub fn position_slot(&self, position: &Pubkey) -> Option<usize> {
+        self.positions.iter().position(|vault_position| {
+            vault_position.is_open() && vault_position.position == *position
+        })
+    }
+
+    /// Id telling apart vaults of the same creator on the same whirlpool.
+    pub fn strategy_id(&self) -> u16 {
+        u16::from_le_bytes(self.strategy_id_seed)
+    }
+
+    /// Seeds of the vault PDA, used to sign as position owner and share mint authority.
+    pub fn signer_seeds(&self) -> [&[u8]; 5] {
+        [
+            Self::SEED,
+            self.whirlpool.as_ref(),
+            self.strategy_id_seed.as_ref(),
+            self.creator.as_ref(),
+            std::slice::from_ref(&self.bump),
+        ]
+    }
+}
+
+pub const BPS_DENOMINATOR: u16 = 10_000;
+
+pub const MAX_VAULT_KEEPERS: usize = 5;
+pub const MAX_VAULT_POSITIONS: usize = 3;
+pub const MAX_REGISTRY_VAULTS: usize = 64;
+
+/// Bounds of the optional swap `rebalance` makes to reach the new range's token ratio.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct RebalanceSwap {
+    /// Sqrt price (Q64.64) the swap may not move the pool past
+    pub sqrt_price_limit: u128,
+    /// Largest shortfall of the swap output versus the pool price before fees, in bps, at most
+    /// the vault's `max_swap_slippage_bps`
+    pub max_slippage_bps: u16,
+}
+
+/// Emitted once `rebalance` has moved the vault's liquidity into new ranges.
+#[event]
+pub struct Rebalanced {
+    pub vault: Pubkey,
+    /// The vault's positions after the rebalance
+    pub positions: Vec<VaultPosition>,
+    /// Liquidity added to each position, in slot order
+    pub liquidity: Vec<u128>,
+    /// Token A left idle in the vault because it did not fit the new ranges' ratio
+    pub leftover_a: u64,
+    /// Token B left idle in the vault because it did not fit the new ranges' ratio
+    pub leftover_b: u64,
+}
+
+/// Every vault created by one authority, across whirlpools and strategies.
+#[account]
+#[derive(InitSpace)]
+pub struct VaultRegistry {
+    pub bump: u8,
+    pub authority: Pubkey,
+    #[max_len(MAX_REGISTRY_VAULTS)]
+    pub vaults: Vec<Pubkey>,
+}
+
+impl VaultRegistry {
+    pub const SEED: &'static [u8] = b"registry";
+    pub const MAX_VAULTS: usize = MAX_REGISTRY_VAULTS;
 }
@@ -0,0 +1,67 @@
+use anchor_lang::prelude::*;
+
+use crate::liquidity_vault::CustomError;
+
+const U64_MASK: u128 = u64::MAX as u128;
+
+/// Multiplies two u128 values into a 256-bit product, returned as (hi, lo).
+fn full_mul(a: u128, b: u128) -> (u128, u128) {
+    let (a1, a0) = (a >> 64, a & U64_MASK);
+    let (b1, b0) = (b >> 64, b & U64_MASK);
+
+    let p00 = a0 * b0;
+    let p01 = a0 * b1;
+    let p10 = a1 * b0;
+    let p11 = a1 * b1;
+
+    // middle column: carries out of the low 128 bits go into hi
+    let mid = (p00 >> 64) + (p01 & U64_MASK) + (p10 & U64_MASK);
+    let lo = (p00 & U64_MASK) | (mid << 64);
+    let hi = p11 + (p01 >> 64) + (p10 >> 64) + (mid >> 64);
+    (hi, lo)
+}
+
+/// Divides the 256-bit value (hi, lo) by `denominator`, returning (quotient, remainder).
+/// The quotient must fit in a u128, i.e. `hi < denominator`.
+fn div_rem_256(hi: u128, lo: u128, denominator: u128) -> Result<(u128, u128)> {
+    require!(denominator != 0, CustomError::DivideByZero);
+    require!(hi < denominator, CustomError::MathOverflow);
+
+    if hi == 0 {
+        return Ok((lo / denominator, lo % denominator));
+    }
+
+    // restoring long division, one bit of `lo` at a time
+    let mut rem = hi;
+    let mut quotient = 0u128;
+    for i in (0..128).rev() {
+        let carry = rem >> 127;
+        rem = (rem << 1) | ((lo >> i) & 1);
+        quotient <<= 1;
+        if carry == 1 || rem >= denominator {
+            rem = rem.wrapping_sub(denominator);
+            quotient |= 1;
+        }
+    }
+    Ok((quotient, rem))
+}
+
+/// Computes `a * b / denominator` rounded down, without overflowing the intermediate product.
+pub fn mul_div_floor(a: u128, b: u128, denominator: u128) -> Result<u128> {
+    let (hi, lo) = full_mul(a, b);
+    let (quotient, _) = div_rem_256(hi, lo, denominator)?;
+    Ok(quotient)
+}
+
+/// Computes `a * b / denominator` rounded up, without overflowing the intermediate product.
+pub fn mul_div_ceil(a: u128, b: u128, denominator: u128) -> Result<u128> {
+    let (hi, lo) = full_mul(a, b);
+    let (quotient, rem) = div_rem_256(hi, lo, denominator)?;
+    if rem == 0 {
+        Ok(quotient)
+    } else {
+        quotient
+            .checked_add(1)
+            .ok_or_else(|| error!(CustomError::MathOverflow))
+    }
+}
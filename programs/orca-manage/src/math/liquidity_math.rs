@@ -0,0 +1,229 @@
+use anchor_lang::prelude::*;
+
+use crate::liquidity_vault::CustomError;
+use crate::math::full_math::{mul_div_ceil, mul_div_floor};
+use crate::math::price_math::Q64;
+
+/// Liquidity obtainable from `amount_a` alone over [sqrt_price_lower, sqrt_price_upper).
+///
+/// L = amount_a * sqrt_lower * sqrt_upper / (sqrt_upper - sqrt_lower)
+pub fn get_liquidity_from_amount_a(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    amount_a: u64,
+) -> Result<u128> {
+    let sqrt_price_diff = sqrt_price_diff(sqrt_price_lower, sqrt_price_upper)?;
+    let sqrt_price_product = mul_div_floor(sqrt_price_lower, sqrt_price_upper, Q64)?;
+    mul_div_floor(amount_a as u128, sqrt_price_product, sqrt_price_diff)
+}
+
+/// Liquidity obtainable from `amount_b` alone over [sqrt_price_lower, sqrt_price_upper).
+///
+/// L = amount_b / (sqrt_upper - sqrt_lower)
+pub fn get_liquidity_from_amount_b(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    amount_b: u64,
+) -> Result<u128> {
+    let sqrt_price_diff = sqrt_price_diff(sqrt_price_lower, sqrt_price_upper)?;
+    mul_div_floor(amount_b as u128, Q64, sqrt_price_diff)
+}
+
+/// Maximum liquidity that `amount_a` and `amount_b` can provide at `sqrt_price` for the given range.
+pub fn get_liquidity_from_amounts(
+    sqrt_price: u128,
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    amount_a: u64,
+    amount_b: u64,
+) -> Result<u128> {
+    if sqrt_price <= sqrt_price_lower {
+        // below the range, the position is entirely token A
+        get_liquidity_from_amount_a(sqrt_price_lower, sqrt_price_upper, amount_a)
+    } else if sqrt_price >= sqrt_price_upper {
+        // above the range, the position is entirely token B
+        get_liquidity_from_amount_b(sqrt_price_lower, sqrt_price_upper, amount_b)
+    } else {
+        let liquidity_a = get_liquidity_from_amount_a(sqrt_price, sqrt_price_upper, amount_a)?;
+        let liquidity_b = get_liquidity_from_amount_b(sqrt_price_lower, sqrt_price, amount_b)?;
+        Ok(liquidity_a.min(liquidity_b))
+    }
+}
+
+/// Token A backing `liquidity` over [sqrt_price_lower, sqrt_price_upper).
+///
+/// amount_a = L * (sqrt_upper - sqrt_lower) / (sqrt_lower * sqrt_upper)
+pub fn get_amount_a_from_liquidity(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<u64> {
+    let sqrt_price_diff = sqrt_price_diff(sqrt_price_lower, sqrt_price_upper)?;
+    let amount_a = if round_up {
+        let scaled = mul_div_ceil(liquidity, sqrt_price_diff, sqrt_price_upper)?;
+        mul_div_ceil(scaled, Q64, sqrt_price_lower)?
+    } else {
+        let scaled = mul_div_floor(liquidity, sqrt_price_diff, sqrt_price_upper)?;
+        mul_div_floor(scaled, Q64, sqrt_price_lower)?
+    };
+    u64::try_from(amount_a).map_err(|_| error!(CustomError::MathOverflow))
+}
+
+/// Token B backing `liquidity` over [sqrt_price_lower, sqrt_price_upper).
+///
+/// amount_b = L * (sqrt_upper - sqrt_lower)
+pub fn get_amount_b_from_liquidity(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<u64> {
+    let sqrt_price_diff = sqrt_price_diff(sqrt_price_lower, sqrt_price_upper)?;
+    let amount_b = if round_up {
+        mul_div_ceil(liquidity, sqrt_price_diff, Q64)?
+    } else {
+        mul_div_floor(liquidity, sqrt_price_diff, Q64)?
+    };
+    u64::try_from(amount_b).map_err(|_| error!(CustomError::MathOverflow))
+}
+
+/// Token A and B amounts backing `liquidity` at `sqrt_price` for the given range.
+pub fn get_amounts_from_liquidity(
+    sqrt_price: u128,
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<(u64, u64)> {
+    if sqrt_price <= sqrt_price_lower {
+        let amount_a =
+            get_amount_a_from_liquidity(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?;
+        Ok((amount_a, 0))
+    } else if sqrt_price >= sqrt_price_upper {
+        let amount_b =
+            get_amount_b_from_liquidity(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?;
+        Ok((0, amount_b))
+    } else {
+        let amount_a =
+            get_amount_a_from_liquidity(sqrt_price, sqrt_price_upper, liquidity, round_up)?;
+        let amount_b =
+            get_amount_b_from_liquidity(sqrt_price_lower, sqrt_price, liquidity, round_up)?;
+        Ok((amount_a, amount_b))
+    }
+}
+
+fn sqrt_price_diff(sqrt_price_lower: u128, sqrt_price_upper: u128) -> Result<u128> {
+    require!(
+        sqrt_price_lower < sqrt_price_upper,
+        CustomError::InvalidSqrtPrice
+    );
+    Ok(sqrt_price_upper - sqrt_price_lower)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::tick_math::sqrt_price_from_tick_index;
+
+    fn range(tick_lower_index: i32, tick_upper_index: i32) -> (u128, u128) {
+        (
+            sqrt_price_from_tick_index(tick_lower_index).unwrap(),
+            sqrt_price_from_tick_index(tick_upper_index).unwrap(),
+        )
+    }
+
+    #[test]
+    fn below_the_range_only_token_a_counts() {
+        let (lower, upper) = range(100, 200);
+        let sqrt_price = sqrt_price_from_tick_index(0).unwrap();
+        let liquidity = get_liquidity_from_amounts(sqrt_price, lower, upper, 1_000_000, 0).unwrap();
+        assert!(liquidity > 0);
+        assert_eq!(
+            get_liquidity_from_amounts(sqrt_price, lower, upper, 1_000_000, 5_000_000).unwrap(),
+            liquidity
+        );
+        let (amount_a, amount_b) =
+            get_amounts_from_liquidity(sqrt_price, lower, upper, liquidity, false).unwrap();
+        assert!(amount_a > 0 && amount_a <= 1_000_000);
+        assert_eq!(amount_b, 0);
+    }
+
+    #[test]
+    fn above_the_range_only_token_b_counts() {
+        let (lower, upper) = range(-200, -100);
+        let sqrt_price = sqrt_price_from_tick_index(0).unwrap();
+        let liquidity = get_liquidity_from_amounts(sqrt_price, lower, upper, 0, 1_000_000).unwrap();
+        assert!(liquidity > 0);
+        assert_eq!(
+            get_liquidity_from_amounts(sqrt_price, lower, upper, 5_000_000, 1_000_000).unwrap(),
+            liquidity
+        );
+        let (amount_a, amount_b) =
+            get_amounts_from_liquidity(sqrt_price, lower, upper, liquidity, false).unwrap();
+        assert_eq!(amount_a, 0);
+        assert!(amount_b > 0 && amount_b <= 1_000_000);
+    }
+
+    #[test]
+    fn inside_the_range_the_scarcer_token_limits_liquidity() {
+        let (lower, upper) = range(-100, 100);
+        let sqrt_price = sqrt_price_from_tick_index(0).unwrap();
+        let balanced =
+            get_liquidity_from_amounts(sqrt_price, lower, upper, 1_000_000, 1_000_000).unwrap();
+        let short_a =
+            get_liquidity_from_amounts(sqrt_price, lower, upper, 500_000, 1_000_000).unwrap();
+        let short_b =
+            get_liquidity_from_amounts(sqrt_price, lower, upper, 1_000_000, 500_000).unwrap();
+        assert!(short_a < balanced && short_b < balanced);
+        assert_eq!(
+            get_liquidity_from_amounts(sqrt_price, lower, upper, 0, 1_000_000).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn amounts_round_trip_through_liquidity() {
+        for (tick_current_index, tick_lower_index, tick_upper_index) in [
+            (0, -100, 100),
+            (-500, -100, 100),
+            (500, -100, 100),
+            (37, -6400, 6400),
+        ] {
+            let (lower, upper) = range(tick_lower_index, tick_upper_index);
+            let sqrt_price = sqrt_price_from_tick_index(tick_current_index).unwrap();
+            let (amount_a, amount_b) = (1_000_000_000, 2_000_000_000);
+            let liquidity =
+                get_liquidity_from_amounts(sqrt_price, lower, upper, amount_a, amount_b).unwrap();
+            let (floor_a, floor_b) =
+                get_amounts_from_liquidity(sqrt_price, lower, upper, liquidity, false).unwrap();
+            let (ceil_a, ceil_b) =
+                get_amounts_from_liquidity(sqrt_price, lower, upper, liquidity, true).unwrap();
+
+            // rounding down never hands back more than was put in, each rounding step costs at most 1
+            assert!(floor_a <= amount_a && floor_b <= amount_b);
+            assert!(floor_a <= ceil_a && ceil_a - floor_a <= 2);
+            assert!(floor_b <= ceil_b && ceil_b - floor_b <= 1);
+
+            // the limiting token is used up to rounding
+            let limiting_slack = (amount_a - floor_a).min(amount_b - floor_b);
+            assert!(
+                limiting_slack <= 1,
+                "slack {limiting_slack} at {tick_current_index}"
+            );
+
+            // and the amounts buy back the same liquidity
+            let again =
+                get_liquidity_from_amounts(sqrt_price, lower, upper, ceil_a, ceil_b).unwrap();
+            assert!(again >= liquidity);
+        }
+    }
+
+    #[test]
+    fn empty_ranges_are_rejected() {
+        let (lower, _) = range(0, 100);
+        assert!(get_liquidity_from_amount_a(lower, lower, 1).is_err());
+        assert!(get_liquidity_from_amount_b(lower, lower, 1).is_err());
+        assert!(get_amounts_from_liquidity(lower, lower, lower, 1, false).is_err());
+    }
+}
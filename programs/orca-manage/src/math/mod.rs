@@ -0,0 +1,5 @@
+pub mod full_math;
+pub mod liquidity_math;
+pub mod price_math;
+pub mod share_math;
+pub mod tick_math;
@@ -0,0 +1,161 @@
+use anchor_lang::prelude::*;
+
+use crate::liquidity_vault::CustomError;
+use crate::math::full_math::mul_div_floor;
+
+pub const Q64_RESOLUTION: u8 = 64;
+pub const Q64: u128 = 1u128 << Q64_RESOLUTION;
+
+/// Largest decimals difference whose power of ten still fits alongside a Q64 scale in a u128.
+const MAX_DECIMALS_DIFF: u32 = 19;
+
+/// Decimal-adjusted price of token A denominated in token B, stored as a Q64.64 fixed-point number.
+///
+/// A whole unit of token A is worth `price.to_x64() / 2^64` whole units of token B.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Price(u128);
+
+impl Price {
+    pub const fn from_x64(price_x64: u128) -> Self {
+        Self(price_x64)
+    }
+
+    pub const fn to_x64(self) -> u128 {
+        self.0
+    }
+
+    /// Derives the price from a Whirlpool `sqrt_price` (Q64.64, in raw token units).
+    ///
+    /// price = (sqrt_price / 2^64)^2 * 10^(decimals_a - decimals_b)
+    pub fn from_sqrt_price_x64(
+        sqrt_price_x64: u128,
+        decimals_a: u8,
+        decimals_b: u8,
+    ) -> Result<Self> {
+        let price_x64 = if decimals_a >= decimals_b {
+            let raw_price_x64 = mul_div_floor(sqrt_price_x64, sqrt_price_x64, Q64)?;
+            raw_price_x64
+                .checked_mul(pow10(decimals_a - decimals_b)?)
+                .ok_or_else(|| error!(CustomError::MathOverflow))?
+        } else {
+            // Q64 * 10^19 < 2^128, so the combined denominator cannot overflow
+            let denominator = Q64 * pow10(decimals_b - decimals_a)?;
+            mul_div_floor(sqrt_price_x64, sqrt_price_x64, denominator)?
+        };
+        Ok(Self(price_x64))
+    }
+
+    /// Converts the price back into a Whirlpool `sqrt_price` (Q64.64, in raw token units), rounded down.
+    pub fn to_sqrt_price_x64(self, decimals_a: u8, decimals_b: u8) -> Result<u128> {
+        let raw_price_x64 = if decimals_a >= decimals_b {
+            self.0 / pow10(decimals_a - decimals_b)?
+        } else {
+            self.0
+                .checked_mul(pow10(decimals_b - decimals_a)?)
+                .ok_or_else(|| error!(CustomError::MathOverflow))?
+        };
+        sqrt_x64(raw_price_x64)
+    }
+}
+
+/// Computes the square root of a Q64.64 as a Q64.64, i.e. floor(sqrt(value_x64 * 2^64)).
+fn sqrt_x64(value_x64: u128) -> Result<u128> {
+    if value_x64 == 0 {
+        return Ok(0);
+    }
+    // Newton's method converges downwards from any starting point above the root
+    let mut root = (isqrt(value_x64) + 1) << 32;
+    loop {
+        let next = (root + mul_div_floor(value_x64, Q64, root)?) / 2;
+        if next >= root {
+            return Ok(root);
+        }
+        root = next;
+    }
+}
+
+fn isqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+    let mut root = 1u128 << (128 - value.leading_zeros()).div_ceil(2);
+    loop {
+        let next = (root + value / root) / 2;
+        if next >= root {
+            return root;
+        }
+        root = next;
+    }
+}
+
+fn pow10(exponent: u8) -> Result<u128> {
+    require!(
+        exponent as u32 <= MAX_DECIMALS_DIFF,
+        CustomError::MathOverflow
+    );
+    Ok(10u128.pow(exponent as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::tick_math::{MAX_SQRT_PRICE_X64, MIN_SQRT_PRICE_X64};
+
+    #[test]
+    fn decimals_scale_the_price_in_both_directions() {
+        // a raw price of 1 is 1000 whole B per A when A has 3 more decimals, 1/1000 with 3 fewer
+        assert_eq!(
+            Price::from_sqrt_price_x64(Q64, 9, 6).unwrap().to_x64(),
+            1_000 * Q64
+        );
+        assert_eq!(
+            Price::from_sqrt_price_x64(Q64, 6, 9).unwrap().to_x64(),
+            Q64 / 1_000
+        );
+        assert_eq!(Price::from_sqrt_price_x64(Q64, 6, 6).unwrap().to_x64(), Q64);
+    }
+
+    #[test]
+    fn sqrt_price_round_trips_through_the_decimals() {
+        assert_eq!(
+            Price::from_x64(1_000 * Q64)
+                .to_sqrt_price_x64(9, 6)
+                .unwrap(),
+            Q64
+        );
+        // 1/1000 is not exact in Q64.64, the round trip rounds down
+        let sqrt_price_x64 = Price::from_x64(Q64 / 1_000)
+            .to_sqrt_price_x64(6, 9)
+            .unwrap();
+        assert_eq!(sqrt_price_x64, Q64 - 309);
+    }
+
+    #[test]
+    fn newton_sqrt_rounds_down() {
+        assert_eq!(sqrt_x64(0).unwrap(), 0);
+        assert_eq!(sqrt_x64(Q64).unwrap(), Q64);
+        assert_eq!(sqrt_x64(4 * Q64).unwrap(), 2 * Q64);
+        // floor(sqrt(2) * 2^64)
+        assert_eq!(sqrt_x64(2 * Q64).unwrap(), 26087635650665564424);
+    }
+
+    #[test]
+    fn sqrt_price_bounds_round_trip_within_price_resolution() {
+        let round_trip = |sqrt_price_x64| {
+            Price::from_sqrt_price_x64(sqrt_price_x64, 0, 0)
+                .unwrap()
+                .to_sqrt_price_x64(0, 0)
+                .unwrap()
+        };
+        assert_eq!(round_trip(MAX_SQRT_PRICE_X64), MAX_SQRT_PRICE_X64 - 1);
+        // the lowest price is below 2^-63, the Q64.64 price keeps only its last bit
+        assert_eq!(round_trip(MIN_SQRT_PRICE_X64), 1 << 32);
+    }
+
+    #[test]
+    fn overflowing_prices_are_rejected() {
+        assert!(Price::from_sqrt_price_x64(MAX_SQRT_PRICE_X64, 1, 0).is_err());
+        assert!(Price::from_x64(u128::MAX).to_sqrt_price_x64(0, 1).is_err());
+        assert!(Price::from_sqrt_price_x64(Q64, 20, 0).is_err());
+    }
+}
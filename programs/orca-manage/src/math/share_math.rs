@@ -0,0 +1,141 @@
+use anchor_lang::prelude::*;
+
+use crate::liquidity_vault::CustomError;
+use crate::math::full_math::mul_div_floor;
+use crate::BPS_DENOMINATOR;
+
+pub const SECONDS_PER_YEAR: u128 = 365 * 24 * 60 * 60;
+
+/// Virtual shares and value added to the vault totals whenever shares are priced.
+///
+/// The first depositor cannot inflate the share price by donating tokens to the vault: the
+/// virtual shares own part of every donation, so the attack costs far more than it can steal.
+pub const VIRTUAL_SHARES: u128 = 1_000;
+pub const VIRTUAL_VALUE: u128 = 1;
+
+/// Shares the first deposit gives up for good, counted in the vault's supply but owned by no one.
+///
+/// They keep the supply large enough that a donation cannot push the share price to where
+/// deposits round down to a handful of shares.
+pub const LOCKED_SHARES: u64 = 10_000_000;
+
+/// Shares minted for a deposit worth `value`, rounded down in favour of the vault.
+pub fn get_shares_for_value(value: u128, total_shares: u64, total_value: u128) -> Result<u64> {
+    let total_value = total_value
+        .checked_add(VIRTUAL_VALUE)
+        .ok_or_else(|| error!(CustomError::MathOverflow))?;
+    let shares = mul_div_floor(value, total_shares as u128 + VIRTUAL_SHARES, total_value)?;
+    u64::try_from(shares).map_err(|_| error!(CustomError::MathOverflow))
+}
+
+/// Splits the shares priced for a deposit into the ones minted to the depositor and the ones
+/// locked, [`LOCKED_SHARES`] when the vault has no shares yet and none otherwise.
+pub fn lock_first_deposit_shares(shares: u64, total_shares: u64) -> Result<(u64, u64)> {
+    if total_shares > 0 {
+        return Ok((shares, 0));
+    }
+    let user_shares = shares
+        .checked_sub(LOCKED_SHARES)
+        .ok_or_else(|| error!(CustomError::DepositTooSmall))?;
+    Ok((user_shares, LOCKED_SHARES))
+}
+
+/// Part of `amount` owned by `shares`, rounded down in favour of the vault.
+pub fn get_amount_for_shares(amount: u128, shares: u64, total_shares: u64) -> Result<u128> {
+    mul_div_floor(
+        amount,
+        shares as u128,
+        total_shares as u128 + VIRTUAL_SHARES,
+    )
+}
+
+/// Shares minted to charge an annual `fee_bps` of the vault over `elapsed` seconds.
+pub fn get_management_fee_shares(total_shares: u64, fee_bps: u16, elapsed: i64) -> Result<u64> {
+    if elapsed <= 0 {
+        return Ok(0);
+    }
+    let shares = mul_div_floor(
+        total_shares as u128 * fee_bps as u128,
+        elapsed as u128,
+        BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR,
+    )?;
+    u64::try_from(shares).map_err(|_| error!(CustomError::MathOverflow))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_deposit_is_priced_against_virtual_totals() {
+        assert_eq!(
+            get_shares_for_value(1_000_000, 0, 0).unwrap(),
+            1_000_000_000
+        );
+        assert_eq!(
+            get_amount_for_shares(1_000_000, 1_000_000_000, 1_000_000_000).unwrap(),
+            999_999
+        );
+    }
+
+    #[test]
+    fn first_deposit_locks_shares() {
+        assert!(lock_first_deposit_shares(LOCKED_SHARES - 1, 0).is_err());
+        assert_eq!(
+            lock_first_deposit_shares(LOCKED_SHARES + 1, 0).unwrap(),
+            (1, LOCKED_SHARES)
+        );
+        assert_eq!(lock_first_deposit_shares(5, 1).unwrap(), (5, 0));
+    }
+
+    #[test]
+    fn donation_attack_is_unprofitable() {
+        // attacker makes the smallest first deposit, then donates to the vault to inflate the
+        // price
+        let attacker_deposit = 10_001u128;
+        let (attacker_shares, locked_shares) =
+            lock_first_deposit_shares(get_shares_for_value(attacker_deposit, 0, 0).unwrap(), 0)
+                .unwrap();
+        let donation = 1_000_000_000u128;
+        let total_value = attacker_deposit + donation;
+
+        // the victim, depositing far less than the donation, loses under 0.1% to rounding
+        let victim_deposit = 1_000_000u128;
+        let victim_shares =
+            get_shares_for_value(victim_deposit, attacker_shares + locked_shares, total_value)
+                .unwrap();
+        let total_shares = attacker_shares + locked_shares + victim_shares;
+        let total_value = total_value + victim_deposit;
+        let victim_payout =
+            get_amount_for_shares(total_value, victim_shares, total_shares).unwrap();
+        assert!(victim_payout <= victim_deposit);
+        assert!((victim_deposit - victim_payout) * 1_000 < victim_deposit);
+
+        // while the attacker loses nearly all of the donation to the locked shares
+        let attacker_payout =
+            get_amount_for_shares(total_value, attacker_shares, total_shares).unwrap();
+        let attacker_loss = attacker_deposit + donation - attacker_payout;
+        assert!(attacker_loss * 1_000 >= donation * 999);
+    }
+
+    #[test]
+    fn shares_round_down_in_favour_of_the_vault() {
+        let shares = get_shares_for_value(10, 1_000, 7).unwrap();
+        let payout = get_amount_for_shares(17, shares, 1_000 + shares).unwrap();
+        assert!(payout <= 10);
+    }
+
+    #[test]
+    fn management_fee_accrues_linearly() {
+        let year = SECONDS_PER_YEAR as i64;
+        assert_eq!(
+            get_management_fee_shares(1_000_000, 200, year).unwrap(),
+            20_000
+        );
+        assert_eq!(
+            get_management_fee_shares(1_000_000, 200, year / 2).unwrap(),
+            10_000
+        );
+        assert_eq!(get_management_fee_shares(1_000_000, 200, 0).unwrap(), 0);
+    }
+}
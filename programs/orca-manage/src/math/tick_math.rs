@@ -0,0 +1,221 @@
+use anchor_lang::prelude::*;
+
+use crate::liquidity_vault::CustomError;
+use crate::math::full_math::mul_div_floor;
+use crate::math::price_math::Price;
+
+// Whirlpool tick bounds, price = 1.0001^tick
+pub const MIN_TICK_INDEX: i32 = -443636;
+pub const MAX_TICK_INDEX: i32 = 443636;
+
+pub const MIN_SQRT_PRICE_X64: u128 = 4295048016;
+pub const MAX_SQRT_PRICE_X64: u128 = 79226673515401279992447579055;
+
+// log_b(2) as Q32.32 where b = sqrt(1.0001)
+const LOG_B_2_X32: i128 = 59543866431248;
+const BIT_PRECISION: u32 = 14;
+// 0.01
+const LOG_B_P_ERR_MARGIN_LOWER_X64: i128 = 184467440737095516;
+// 2^-BIT_PRECISION / log_2(b) + 0.01
+const LOG_B_P_ERR_MARGIN_UPPER_X64: i128 = 15793534762490258745;
+
+// sqrt(1.0001)^(2^i) as Q32.96, rounded down
+const SQRT_PRICE_POSITIVE_X96: [u128; 19] = [
+    79232123823359799118286999567,
+    79236085330515764027303304731,
+    79244008939048815603706035061,
+    79259858533276714757314932305,
+    79291567232598584799939703904,
+    79355022692464371645785046466,
+    79482085999252804386437311141,
+    79736823300114093921829183326,
+    80248749790819932309965073892,
+    81282483887344747381513967011,
+    83390072131320151908154831281,
+    87770609709833776024991924138,
+    97234110755111693312479820773,
+    119332217159966728226237229890,
+    179736315981702064433883588727,
+    407748233172238350107850275304,
+    2098478828474011932436660412517,
+    55581415166113811149459800483533,
+    38992368544603139932233054999993551,
+];
+
+// sqrt(1.0001)^-(2^i) as Q64.64, rounded down
+const SQRT_PRICE_NEGATIVE_X64: [u128; 19] = [
+    18445821805675392311,
+    18444899583751176498,
+    18443055278223354162,
+    18439367220385604838,
+    18431993317065449817,
+    18417254355718160513,
+    18387811781193591352,
+    18329067761203520168,
+    18212142134806087854,
+    17980523815641551639,
+    17526086738831147013,
+    16651378430235024244,
+    15030750278693429944,
+    12247334978882834399,
+    8131365268884726200,
+    3584323654723342297,
+    696457651847595233,
+    26294789957452057,
+    37481735321082,
+];
+
+/// Computes sqrt(1.0001^tick) as a Q64.64, matching the Whirlpool program's rounding.
+pub fn sqrt_price_from_tick_index(tick_index: i32) -> Result<u128> {
+    require!(
+        (MIN_TICK_INDEX..=MAX_TICK_INDEX).contains(&tick_index),
+        CustomError::InvalidTickIndex
+    );
+
+    let abs_tick = tick_index.unsigned_abs();
+    if tick_index >= 0 {
+        let mut ratio_x96 = 1u128 << 96;
+        for (i, factor) in SQRT_PRICE_POSITIVE_X96.iter().enumerate() {
+            if abs_tick & (1 << i) != 0 {
+                ratio_x96 = mul_div_floor(ratio_x96, *factor, 1u128 << 96)?;
+            }
+        }
+        Ok(ratio_x96 >> 32)
+    } else {
+        let mut ratio_x64 = 1u128 << 64;
+        for (i, factor) in SQRT_PRICE_NEGATIVE_X64.iter().enumerate() {
+            if abs_tick & (1 << i) != 0 {
+                // both operands are <= 2^64, the product fits in a u128
+                ratio_x64 = (ratio_x64 * factor) >> 64;
+            }
+        }
+        Ok(ratio_x64)
+    }
+}
+
+/// Computes the greatest tick whose sqrt price is <= `sqrt_price_x64`.
+pub fn tick_index_from_sqrt_price(sqrt_price_x64: u128) -> Result<i32> {
+    require!(
+        (MIN_SQRT_PRICE_X64..=MAX_SQRT_PRICE_X64).contains(&sqrt_price_x64),
+        CustomError::InvalidSqrtPrice
+    );
+
+    // integer part of log2(sqrt_price)
+    let msb = 127 - sqrt_price_x64.leading_zeros();
+    let log2p_integer_x32 = (msb as i128 - 64) << 32;
+
+    // fractional part of log2(sqrt_price), one bit per squaring of r = sqrt_price / 2^msb
+    let mut r = if msb >= 64 {
+        sqrt_price_x64 >> (msb - 63)
+    } else {
+        sqrt_price_x64 << (63 - msb)
+    };
+    let mut bit: i128 = 0x8000_0000_0000_0000;
+    let mut log2p_fraction_x64: i128 = 0;
+    for _ in 0..BIT_PRECISION {
+        r *= r;
+        let is_r_more_than_two = (r >> 127) as u32;
+        r >>= 63 + is_r_more_than_two;
+        log2p_fraction_x64 += bit * is_r_more_than_two as i128;
+        bit >>= 1;
+    }
+    let log2p_x32 = log2p_integer_x32 + (log2p_fraction_x64 >> 32);
+
+    // change of base from 2 to b
+    let logbp_x64 = log2p_x32 * LOG_B_2_X32;
+
+    // the estimate can be off by one, settle it against the exact sqrt price of the higher tick
+    let tick_low = ((logbp_x64 - LOG_B_P_ERR_MARGIN_LOWER_X64) >> 64) as i32;
+    let tick_high = ((logbp_x64 + LOG_B_P_ERR_MARGIN_UPPER_X64) >> 64) as i32;
+    if tick_low == tick_high || sqrt_price_from_tick_index(tick_high)? > sqrt_price_x64 {
+        Ok(tick_low)
+    } else {
+        Ok(tick_high)
+    }
+}
+
+/// Converts a decimal-adjusted price into the tick it falls in, clamped to the Whirlpool bounds.
+pub fn tick_index_from_price(price: Price, decimals_a: u8, decimals_b: u8) -> Result<i32> {
+    let sqrt_price_x64 = price
+        .to_sqrt_price_x64(decimals_a, decimals_b)?
+        .clamp(MIN_SQRT_PRICE_X64, MAX_SQRT_PRICE_X64);
+    tick_index_from_sqrt_price(sqrt_price_x64)
+}
+
+/// Rounds `tick_index` to a multiple of `tick_spacing`, down unless `round_up` is set,
+/// staying within the initializable tick bounds of the pool.
+pub fn get_initializable_tick_index(tick_index: i32, tick_spacing: u16, round_up: bool) -> i32 {
+    let spacing = tick_spacing as i32;
+    let min_initializable = MIN_TICK_INDEX / spacing * spacing;
+    let max_initializable = MAX_TICK_INDEX / spacing * spacing;
+
+    let mut rounded = tick_index.div_euclid(spacing) * spacing;
+    if round_up && rounded != tick_index {
+        rounded += spacing;
+    }
+    rounded.clamp(min_initializable, max_initializable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqrt_price_round_trips_at_the_bounds() {
+        for (tick_index, sqrt_price_x64) in [
+            (MIN_TICK_INDEX, MIN_SQRT_PRICE_X64),
+            (0, 1u128 << 64),
+            (MAX_TICK_INDEX, MAX_SQRT_PRICE_X64),
+        ] {
+            assert_eq!(
+                sqrt_price_from_tick_index(tick_index).unwrap(),
+                sqrt_price_x64
+            );
+            assert_eq!(
+                tick_index_from_sqrt_price(sqrt_price_x64).unwrap(),
+                tick_index
+            );
+        }
+    }
+
+    #[test]
+    fn sqrt_price_between_ticks_maps_to_the_lower_tick() {
+        for tick_index in [MIN_TICK_INDEX, -1, 0, 1, MAX_TICK_INDEX - 1] {
+            let sqrt_price_x64 = sqrt_price_from_tick_index(tick_index + 1).unwrap() - 1;
+            assert_eq!(
+                tick_index_from_sqrt_price(sqrt_price_x64).unwrap(),
+                tick_index
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_bounds_inputs_are_rejected() {
+        assert!(sqrt_price_from_tick_index(MIN_TICK_INDEX - 1).is_err());
+        assert!(sqrt_price_from_tick_index(MAX_TICK_INDEX + 1).is_err());
+        assert!(tick_index_from_sqrt_price(MIN_SQRT_PRICE_X64 - 1).is_err());
+        assert!(tick_index_from_sqrt_price(MAX_SQRT_PRICE_X64 + 1).is_err());
+    }
+
+    #[test]
+    fn negative_ticks_round_towards_their_spacing_multiples() {
+        assert_eq!(get_initializable_tick_index(-1, 64, false), -64);
+        assert_eq!(get_initializable_tick_index(-1, 64, true), 0);
+        assert_eq!(get_initializable_tick_index(-100, 64, false), -128);
+        assert_eq!(get_initializable_tick_index(-100, 64, true), -64);
+        assert_eq!(get_initializable_tick_index(-128, 64, false), -128);
+        assert_eq!(get_initializable_tick_index(-128, 64, true), -128);
+    }
+
+    #[test]
+    fn rounding_stays_within_the_initializable_bounds() {
+        assert_eq!(
+            get_initializable_tick_index(MIN_TICK_INDEX, 64, false),
+            -443584
+        );
+        assert_eq!(
+            get_initializable_tick_index(MAX_TICK_INDEX, 64, true),
+            443584
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use anchor_lang::prelude::*;
+use whirlpool_cpi::state::{Position, Whirlpool};
+
+use crate::liquidity_vault::CustomError;
+use crate::math::{full_math::mul_div_floor, liquidity_math, price_math::Q64, tick_math};
+
+/// Token A/B the vault owns, and their combined value in token B at the pool's current price.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VaultNav {
+    pub amount_a: u128,
+    pub amount_b: u128,
+    pub value: u128,
+}
+
+/// Values the vault's positions liquidity, the fees they are owed and its idle balances.
+///
+/// `positions` should have had `update_fees_and_rewards` applied in the same transaction,
+/// otherwise the owed fees lag behind. Owed rewards are left out: withdrawals do not collect
+/// them, they only count once harvested into the vault's token accounts.
+pub fn get_vault_nav<'a>(
+    whirlpool: &Whirlpool,
+    positions: impl IntoIterator<Item = &'a Position>,
+    idle_a: u64,
+    idle_b: u64,
+) -> Result<VaultNav> {
+    let mut amount_a = idle_a as u128;
+    let mut amount_b = idle_b as u128;
+
+    for position in positions {
+        let (liquidity_a, liquidity_b) = liquidity_math::get_amounts_from_liquidity(
+            whirlpool.sqrt_price,
+            tick_math::sqrt_price_from_tick_index(position.tick_lower_index)?,
+            tick_math::sqrt_price_from_tick_index(position.tick_upper_index)?,
+            position.liquidity,
+            false,
+        )?;
+        amount_a += liquidity_a as u128 + position.fee_owed_a as u128;
+        amount_b += liquidity_b as u128 + position.fee_owed_b as u128;
+    }
+
+    let value = amount_b
+        .checked_add(value_in_token_b(amount_a, whirlpool.sqrt_price)?)
+        .ok_or_else(|| error!(CustomError::MathOverflow))?;
+    Ok(VaultNav {
+        amount_a,
+        amount_b,
+        value,
+    })
+}
+
+/// Values an amount of token A in token B, price = (sqrt_price / 2^64)^2.
+pub fn value_in_token_b(amount_a: u128, sqrt_price_x64: u128) -> Result<u128> {
+    let scaled = mul_div_floor(amount_a, sqrt_price_x64, Q64)?;
+    mul_div_floor(scaled, sqrt_price_x64, Q64)
+}
+
+/// Values an amount of token B in token A, the inverse of [`value_in_token_b`].
+pub fn value_in_token_a(amount_b: u128, sqrt_price_x64: u128) -> Result<u128> {
+    let scaled = mul_div_floor(amount_b, Q64, sqrt_price_x64)?;
+    mul_div_floor(scaled, Q64, sqrt_price_x64)
+}
+
+/// A position range and the share of the vault's funds it should hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WeightedRange {
+    pub sqrt_price_lower_x64: u128,
+    pub sqrt_price_upper_x64: u128,
+    pub weight_bps: u16,
+}
+
+impl WeightedRange {
+    pub fn new(tick_lower_index: i32, tick_upper_index: i32, weight_bps: u16) -> Result<Self> {
+        Ok(Self {
+            sqrt_price_lower_x64: tick_math::sqrt_price_from_tick_index(tick_lower_index)?,
+            sqrt_price_upper_x64: tick_math::sqrt_price_from_tick_index(tick_upper_index)?,
+            weight_bps,
+        })
+    }
+}
+
+/// Parts of the vault's value `range` wants held as token A and as token B, its weight scaled by
+/// 2^64 and split along the range's ratio at the current price.
+///
+/// Per unit of liquidity the range holds `(upper - p) * p / upper` of value as token A and
+/// `p - lower` as token B, with `p` the current sqrt price clamped to the range.
+fn get_value_shares(sqrt_price_x64: u128, range: &WeightedRange) -> Result<(u128, u128)> {
+    let sqrt_price = sqrt_price_x64.clamp(range.sqrt_price_lower_x64, range.sqrt_price_upper_x64);
+    let weight_a = mul_div_floor(
+        range.sqrt_price_upper_x64 - sqrt_price,
+        sqrt_price,
+        range.sqrt_price_upper_x64,
+    )?;
+    let weight_b = sqrt_price - range.sqrt_price_lower_x64;
+    let total_weight = weight_a
+        .checked_add(weight_b)
+        .ok_or_else(|| error!(CustomError::MathOverflow))?;
+    if total_weight == 0 {
+        return Ok((0, 0));
+    }
+
+    let share = range.weight_bps as u128 * Q64;
+    let share_a = mul_div_floor(share, weight_a, total_weight)?;
+    Ok((share_a, share - share_a))
+}
+
+/// Swap that moves idle balances to the token ratio position ranges need at the current price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapToRatio {
+    pub a_to_b: bool,
+    pub amount_in: u64,
+    /// Output at the current price, before pool fees and price impact
+    pub expected_out: u64,
+}
+
+/// Returns the swap that brings `amount_a`/`amount_b` to the value split liquidity in `ranges`
+/// needs, each range holding its weight of the value, or `None` when the balances already match.
+pub fn get_swap_to_ratio(
+    amount_a: u64,
+    amount_b: u64,
+    sqrt_price_x64: u128,
+    ranges: &[WeightedRange],
+) -> Result<Option<SwapToRatio>> {
+    let (mut share_a, mut share_b) = (0u128, 0u128);
+    for range in ranges {
+        let (range_share_a, range_share_b) = get_value_shares(sqrt_price_x64, range)?;
+        share_a += range_share_a;
+        share_b += range_share_b;
+    }
+    let total_share = share_a + share_b;
+    if total_share == 0 {
+        return Ok(None);
+    }
+
+    let value_a = value_in_token_b(amount_a as u128, sqrt_price_x64)?;
+    let total_value = value_a
+        .checked_add(amount_b as u128)
+        .ok_or_else(|| error!(CustomError::MathOverflow))?;
+    let target_value_a = mul_div_floor(total_value, share_a, total_share)?;
+
+    let (a_to_b, amount_in, expected_out) = if value_a > target_value_a {
+        let excess_value = value_a - target_value_a;
+        (
+            true,
+            value_in_token_a(excess_value, sqrt_price_x64)?,
+            excess_value,
+        )
+    } else {
+        let missing_value = target_value_a - value_a;
+        (
+            false,
+            missing_value,
+            value_in_token_a(missing_value, sqrt_price_x64)?,
+        )
+    };
+    if amount_in == 0 || expected_out == 0 {
+        return Ok(None);
+    }
+    Ok(Some(SwapToRatio {
+        a_to_b,
+        amount_in: u64::try_from(amount_in).map_err(|_| error!(CustomError::MathOverflow))?,
+        expected_out: u64::try_from(expected_out).map_err(|_| error!(CustomError::MathOverflow))?,
+    }))
+}
+
+/// Splits `amount_a`/`amount_b` between `ranges`, each range getting the part of either token
+/// its weight and ratio call for. The parts add up to at most the amounts.
+pub fn split_amounts(
+    amount_a: u64,
+    amount_b: u64,
+    sqrt_price_x64: u128,
+    ranges: &[WeightedRange],
+) -> Result<Vec<(u64, u64)>> {
+    let shares = ranges
+        .iter()
+        .map(|range| get_value_shares(sqrt_price_x64, range))
+        .collect::<Result<Vec<_>>>()?;
+    let total_share_a: u128 = shares.iter().map(|(share_a, _)| share_a).sum();
+    let total_share_b: u128 = shares.iter().map(|(_, share_b)| share_b).sum();
+
+    let part = |amount: u64, share: u128, total_share: u128| -> Result<u64> {
+        if total_share == 0 {
+            return Ok(0);
+        }
+        Ok(mul_div_floor(amount as u128, share, total_share)? as u64)
+    };
+    shares
+        .iter()
+        .map(|&(share_a, share_b)| {
+            Ok((
+                part(amount_a, share_a, total_share_a)?,
+                part(amount_b, share_b, total_share_b)?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRICE_ONE: u128 = 1 << 64;
+
+    fn weighted(tick_lower_index: i32, tick_upper_index: i32, weight_bps: u16) -> WeightedRange {
+        WeightedRange::new(tick_lower_index, tick_upper_index, weight_bps).unwrap()
+    }
+
+    #[test]
+    fn value_shares_follow_the_price_position_in_the_range() {
+        let full_weight = 10_000 * Q64;
+
+        // below the range everything is held as token A, above it as token B
+        let below = get_value_shares(PRICE_ONE, &weighted(100, 200, 10_000)).unwrap();
+        assert_eq!(below, (full_weight, 0));
+        let above = get_value_shares(PRICE_ONE, &weighted(-200, -100, 10_000)).unwrap();
+        assert_eq!(above, (0, full_weight));
+
+        // a symmetric range around the price splits the value about evenly
+        let (share_a, share_b) = get_value_shares(PRICE_ONE, &weighted(-100, 100, 5_000)).unwrap();
+        assert_eq!(share_a + share_b, 5_000 * Q64);
+        assert!(share_a.abs_diff(share_b) < share_a / 100);
+    }
+
+    #[test]
+    fn balances_already_at_the_ratio_need_no_swap() {
+        let ranges = [weighted(-100, 100, 10_000)];
+        let splits = split_amounts(1_000_000, 1_000_000, PRICE_ONE, &ranges).unwrap();
+        assert_eq!(splits, vec![(1_000_000, 1_000_000)]);
+
+        let swap = get_swap_to_ratio(1_000_000, 1_000_000, PRICE_ONE, &ranges).unwrap();
+        if let Some(swap) = swap {
+            assert!(swap.amount_in <= 1_000_000 / 100);
+        }
+        assert_eq!(
+            get_swap_to_ratio(1_000, 1_000, PRICE_ONE, &[]).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn out_of_range_targets_swap_everything_to_one_token() {
+        // a range above the price holds only token A
+        let swap = get_swap_to_ratio(0, 1_000_000, PRICE_ONE, &[weighted(100, 200, 10_000)])
+            .unwrap()
+            .unwrap();
+        assert!(!swap.a_to_b);
+        assert_eq!(swap.amount_in, 1_000_000);
+        assert_eq!(swap.expected_out, 1_000_000);
+
+        // a range below the price holds only token B
+        let swap = get_swap_to_ratio(1_000_000, 0, PRICE_ONE, &[weighted(-200, -100, 10_000)])
+            .unwrap()
+            .unwrap();
+        assert!(swap.a_to_b);
+        assert_eq!(swap.amount_in, 1_000_000);
+        assert_eq!(swap.expected_out, 1_000_000);
+    }
+
+    #[test]
+    fn swap_moves_value_towards_the_weighted_ratio() {
+        // half the weight wants only token A, half only token B
+        let ranges = [weighted(100, 200, 5_000), weighted(-200, -100, 5_000)];
+        let swap = get_swap_to_ratio(0, 2_000_000, PRICE_ONE, &ranges)
+            .unwrap()
+            .unwrap();
+        assert!(!swap.a_to_b);
+        assert_eq!(swap.amount_in, 1_000_000);
+
+        // at a price of 4 B per A, half the value of an A-only vault is half its A
+        let ranges = [
+            weighted(14_000, 14_100, 5_000),
+            weighted(13_600, 13_700, 5_000),
+        ];
+        let swap = get_swap_to_ratio(1_000_000, 0, 2 * PRICE_ONE, &ranges)
+            .unwrap()
+            .unwrap();
+        assert!(swap.a_to_b);
+        assert_eq!(swap.amount_in, 500_000);
+        assert_eq!(swap.expected_out, 2_000_000);
+    }
+
+    #[test]
+    fn multi_range_splits_never_exceed_the_amounts() {
+        let ranges = [
+            weighted(-6400, 6400, 3_333),
+            weighted(-640, 640, 3_333),
+            weighted(64, 6400, 1_667),
+            weighted(-6400, -64, 1_667),
+        ];
+        for (amount_a, amount_b) in [(1_000_000, 1_000_000), (7, 13), (u64::MAX / 2, 1), (0, 0)] {
+            let splits = split_amounts(amount_a, amount_b, PRICE_ONE, &ranges).unwrap();
+            assert_eq!(splits.len(), ranges.len());
+            let total_a: u128 = splits.iter().map(|&(a, _)| a as u128).sum();
+            let total_b: u128 = splits.iter().map(|&(_, b)| b as u128).sum();
+            assert!(total_a <= amount_a as u128 && total_b <= amount_b as u128);
+            // each split only drops the rounding remainder
+            assert!(amount_a as u128 - total_a < ranges.len() as u128);
+            assert!(amount_b as u128 - total_b < ranges.len() as u128);
+        }
+
+        // out of range ranges take none of the token they do not hold
+        let splits = split_amounts(1_000, 1_000, PRICE_ONE, &ranges).unwrap();
+        assert_eq!(splits[2].1, 0);
+        assert_eq!(splits[3].0, 0);
+    }
+}
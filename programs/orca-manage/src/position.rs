@@ -0,0 +1,579 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::TokenAccount;
+use whirlpool_cpi::state::Position;
+
+use crate::liquidity_vault::CustomError;
+use crate::nav::WeightedRange;
+use crate::strategy::Strategy;
+use crate::{Vault, BPS_DENOMINATOR, MAX_VAULT_POSITIONS};
+
+/// Which Whirlpool instruction opens the vault's positions, closing uses the matching one.
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace,
+)]
+pub enum PositionKind {
+    /// `open_position`, an SPL Token NFT without metadata
+    #[default]
+    Plain,
+    /// `open_position_with_metadata`, an SPL Token NFT with Metaplex metadata wallets can show
+    WithMetadata,
+    /// `open_position_with_token_extensions`, a Token-2022 NFT with the token metadata extension
+    TokenExtensions,
+}
+
+/// Range policy and weight of one of the vault's positions, as configured at init.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PositionLayer {
+    pub strategy: Strategy,
+    /// Share of the vault's funds deployed in the position, in bps
+    pub weight_bps: u16,
+}
+
+impl PositionLayer {
+    /// Checks every strategy and that the weights are positive and add up to 100%.
+    pub fn validate_layers(layers: &[PositionLayer]) -> Result<()> {
+        require!(
+            !layers.is_empty() && layers.len() <= MAX_VAULT_POSITIONS,
+            CustomError::InvalidPositionLayers
+        );
+        let mut total_weight_bps = 0u32;
+        for layer in layers {
+            layer.strategy.validate()?;
+            require!(layer.weight_bps > 0, CustomError::InvalidPositionLayers);
+            total_weight_bps += layer.weight_bps as u32;
+        }
+        require!(
+            total_weight_bps == BPS_DENOMINATOR as u32,
+            CustomError::InvalidPositionLayers
+        );
+        Ok(())
+    }
+}
+
+/// One slot of the vault's position ladder: its layer and the position currently open for it.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct VaultPosition {
+    /// Range policy `rebalance` reopens the position with
+    pub strategy: Strategy,
+    /// Share of the vault's funds deployed in the position, in bps
+    pub weight_bps: u16,
+    /// Open Whirlpool position, `Pubkey::default()` while none is open
+    pub position: Pubkey,
+    pub position_mint: Pubkey,
+    pub tick_lower_index: i32,
+    pub tick_upper_index: i32,
+}
+
+impl VaultPosition {
+    pub fn new(layer: PositionLayer) -> Self {
+        Self {
+            strategy: layer.strategy,
+            weight_bps: layer.weight_bps,
+            position: Pubkey::default(),
+            position_mint: Pubkey::default(),
+            tick_lower_index: 0,
+            tick_upper_index: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.position != Pubkey::default()
+    }
+
+    /// Tracks a newly opened position in this slot.
+    pub fn open(
+        &mut self,
+        position: Pubkey,
+        position_mint: Pubkey,
+        tick_lower_index: i32,
+        tick_upper_index: i32,
+    ) {
+        self.position = position;
+        self.position_mint = position_mint;
+        self.tick_lower_index = tick_lower_index;
+        self.tick_upper_index = tick_upper_index;
+    }
+
+    /// Range of the slot's position, carrying the slot's weight.
+    pub fn weighted_range(&self) -> Result<WeightedRange> {
+        WeightedRange::new(
+            self.tick_lower_index,
+            self.tick_upper_index,
+            self.weight_bps,
+        )
+    }
+
+    /// Forgets the slot's position once it has been closed.
+    pub fn close(&mut self) {
+        self.position = Pubkey::default();
+        self.position_mint = Pubkey::default();
+    }
+}
+
+/// Remaining accounts taken by each open position: `position`, `position_mint`,
+/// `position_token_account`, `tick_array_lower` and `tick_array_upper`.
+pub const POSITION_ACCOUNTS_LEN: usize = 5;
+
+/// Remaining accounts taken by each position `rebalance` opens: `position`, `position_mint`
+/// (signer), `position_token_account`, `tick_array_lower` and `tick_array_upper`, followed by
+/// `position_metadata_account` in `WithMetadata` vaults only.
+pub const NEW_POSITION_ACCOUNTS_LEN: usize = 5;
+
+/// Accounts of one open position of the vault.
+pub struct PositionAccounts<'info> {
+    /// Index of the position in `vault.positions`
+    pub slot: usize,
+    pub position: Account<'info, Position>,
+    pub position_mint: AccountInfo<'info>,
+    pub position_token_account: AccountInfo<'info>,
+    pub tick_array_lower: AccountInfo<'info>,
+    pub tick_array_upper: AccountInfo<'info>,
+}
+
+/// Loads the accounts of every open position of `vault`, in slot order, from the front of
+/// `remaining_accounts`. Returns them with the remaining accounts that follow.
+pub fn load_positions<'info>(
+    vault: &Account<'info, Vault>,
+    remaining_accounts: &'info [AccountInfo<'info>],
+) -> Result<(Vec<PositionAccounts<'info>>, &'info [AccountInfo<'info>])> {
+    let mut positions = Vec::new();
+    let mut remaining_accounts = remaining_accounts;
+    for (slot, vault_position) in vault.positions.iter().enumerate() {
+        if !vault_position.is_open() {
+            continue;
+        }
+        let Some((
+            [position, position_mint, position_token_account, tick_array_lower, tick_array_upper],
+            rest,
+        )) = remaining_accounts.split_first_chunk::<POSITION_ACCOUNTS_LEN>()
+        else {
+            return err!(CustomError::InvalidPositionAccounts);
+        };
+        require_keys_eq!(
+            position.key(),
+            vault_position.position,
+            CustomError::InvalidPositionAccounts
+        );
+        require_keys_eq!(
+            position_mint.key(),
+            vault_position.position_mint,
+            CustomError::InvalidPositionAccounts
+        );
+        let token_account = InterfaceAccount::<TokenAccount>::try_from(position_token_account)?;
+        require!(
+            token_account.mint == vault_position.position_mint
+                && token_account.amount == 1
+                && token_account.owner == vault.key(),
+            CustomError::InvalidPositionAccounts
+        );
+
+        positions.push(PositionAccounts {
+            slot,
+            position: Account::try_from(position)?,
+            position_mint: position_mint.clone(),
+            position_token_account: position_token_account.clone(),
+            tick_array_lower: tick_array_lower.clone(),
+            tick_array_upper: tick_array_upper.clone(),
+        });
+        remaining_accounts = rest;
+    }
+    Ok((positions, remaining_accounts))
+}
+
+/// Accounts of a position `rebalance` opens, initialized by whirlpool.
+pub struct NewPositionAccounts<'info> {
+    pub position: &'info AccountInfo<'info>,
+    pub position_mint: AccountInfo<'info>,
+    pub position_token_account: AccountInfo<'info>,
+    pub tick_array_lower: AccountInfo<'info>,
+    pub tick_array_upper: AccountInfo<'info>,
+    /// `WithMetadata` vaults only
+    pub position_metadata_account: Option<AccountInfo<'info>>,
+}
+
+/// Takes the accounts of one new position for every slot of `vault` from the front of
+/// `remaining_accounts`, with a metadata account each when the vault's positions carry
+/// metadata. Returns them with the remaining accounts that follow.
+pub fn load_new_positions<'info>(
+    vault: &Vault,
+    remaining_accounts: &'info [AccountInfo<'info>],
+) -> Result<(Vec<NewPositionAccounts<'info>>, &'info [AccountInfo<'info>])> {
+    let mut positions = Vec::new();
+    let mut remaining_accounts = remaining_accounts;
+    for _ in &vault.positions {
+        let Some((
+            [position, position_mint, position_token_account, tick_array_lower, tick_array_upper],
+            rest,
+        )) = remaining_accounts.split_first_chunk::<NEW_POSITION_ACCOUNTS_LEN>()
+        else {
+            return err!(CustomError::InvalidPositionAccounts);
+        };
+        let (position_metadata_account, rest) = match vault.position_kind {
+            PositionKind::WithMetadata => {
+                let Some((position_metadata_account, rest)) = rest.split_first() else {
+                    return err!(CustomError::InvalidPositionAccounts);
+                };
+                (Some(position_metadata_account.clone()), rest)
+            }
+            PositionKind::Plain | PositionKind::TokenExtensions => (None, rest),
+        };
+        positions.push(NewPositionAccounts {
+            position,
+            position_mint: position_mint.clone(),
+            position_token_account: position_token_account.clone(),
+            tick_array_lower: tick_array_lower.clone(),
+            tick_array_upper: tick_array_upper.clone(),
+            position_metadata_account,
+        });
+        remaining_accounts = rest;
+    }
+    Ok((positions, remaining_accounts))
+}
+
+/// Pool side accounts of the liquidity and fee CPIs the vault makes on its positions.
+pub struct PoolAccounts<'info> {
+    pub whirlpool_program: AccountInfo<'info>,
+    pub whirlpool: AccountInfo<'info>,
+    /// Position authority
+    pub vault: AccountInfo<'info>,
+    pub token_mint_a: AccountInfo<'info>,
+    pub token_mint_b: AccountInfo<'info>,
+    pub token_owner_account_a: AccountInfo<'info>,
+    pub token_owner_account_b: AccountInfo<'info>,
+    pub token_vault_a: AccountInfo<'info>,
+    pub token_vault_b: AccountInfo<'info>,
+    pub token_program_a: AccountInfo<'info>,
+    pub token_program_b: AccountInfo<'info>,
+    pub memo_program: AccountInfo<'info>,
+}
+
+/// Updates the fees and rewards `position` is owed, a no-op without liquidity.
+pub fn update_fees_and_rewards<'info>(
+    whirlpool_program: AccountInfo<'info>,
+    whirlpool: AccountInfo<'info>,
+    position: &mut PositionAccounts<'info>,
+) -> Result<()> {
+    if position.position.liquidity == 0 {
+        return Ok(());
+    }
+    let cpi_accounts_update_fees_and_rewards = whirlpool_cpi::cpi::accounts::UpdateFeesAndRewards {
+        whirlpool,
+        position: position.position.to_account_info(),
+        tick_array_lower: position.tick_array_lower.clone(),
+        tick_array_upper: position.tick_array_upper.clone(),
+    };
+    let cpi_ctx_update_fees_and_rewards =
+        CpiContext::new(whirlpool_program, cpi_accounts_update_fees_and_rewards);
+
+    // execute CPI
+    msg!("CPI: whirlpool update_fees_and_rewards instruction");
+    whirlpool_cpi::cpi::update_fees_and_rewards(cpi_ctx_update_fees_and_rewards)?;
+    position.position.reload()
+}
+
+impl<'info> PoolAccounts<'info> {
+    /// Collects the fees `position` is owed into the vault's token accounts.
+    pub fn collect_fees(
+        &self,
+        position: &PositionAccounts<'info>,
+        signer_seeds: &[&[&[u8]]],
+    ) -> Result<()> {
+        let cpi_accounts_collect_fees = whirlpool_cpi::cpi::accounts::CollectFeesV2 {
+            whirlpool: self.whirlpool.clone(),
+            position_authority: self.vault.clone(),
+            position: position.position.to_account_info(),
+            position_token_account: position.position_token_account.clone(),
+            token_mint_a: self.token_mint_a.clone(),
+            token_mint_b: self.token_mint_b.clone(),
+            token_owner_account_a: self.token_owner_account_a.clone(),
+            token_vault_a: self.token_vault_a.clone(),
+            token_owner_account_b: self.token_owner_account_b.clone(),
+            token_vault_b: self.token_vault_b.clone(),
+            token_program_a: self.token_program_a.clone(),
+            token_program_b: self.token_program_b.clone(),
+            memo_program: self.memo_program.clone(),
+        };
+        let cpi_ctx_collect_fees = CpiContext::new_with_signer(
+            self.whirlpool_program.clone(),
+            cpi_accounts_collect_fees,
+            signer_seeds,
+        );
+
+        // execute CPI
+        msg!("CPI: whirlpool collect_fees_v2 instruction");
+        whirlpool_cpi::cpi::collect_fees_v2(cpi_ctx_collect_fees, None)
+    }
+
+    fn modify_liquidity_accounts(
+        &self,
+        position: &PositionAccounts<'info>,
+    ) -> whirlpool_cpi::cpi::accounts::ModifyLiquidityV2<'info> {
+        whirlpool_cpi::cpi::accounts::ModifyLiquidityV2 {
+            whirlpool: self.whirlpool.clone(),
+            token_program_a: self.token_program_a.clone(),
+            token_program_b: self.token_program_b.clone(),
+            memo_program: self.memo_program.clone(),
+            position_authority: self.vault.clone(),
+            position: position.position.to_account_info(),
+            position_token_account: position.position_token_account.clone(),
+            token_mint_a: self.token_mint_a.clone(),
+            token_mint_b: self.token_mint_b.clone(),
+            token_owner_account_a: self.token_owner_account_a.clone(),
+            token_owner_account_b: self.token_owner_account_b.clone(),
+            token_vault_a: self.token_vault_a.clone(),
+            token_vault_b: self.token_vault_b.clone(),
+            tick_array_lower: position.tick_array_lower.clone(),
+            tick_array_upper: position.tick_array_upper.clone(),
+        }
+    }
+
+    /// Adds `liquidity` to `position` from the vault's token accounts.
+    pub fn increase_liquidity(
+        &self,
+        position: &PositionAccounts<'info>,
+        signer_seeds: &[&[&[u8]]],
+        liquidity: u128,
+        token_max_a: u64,
+        token_max_b: u64,
+    ) -> Result<()> {
+        let cpi_ctx_increase_liquidity = CpiContext::new_with_signer(
+            self.whirlpool_program.clone(),
+            self.modify_liquidity_accounts(position),
+            signer_seeds,
+        );
+
+        // execute CPI
+        msg!("CPI: whirlpool increase_liquidity_v2 instruction");
+        whirlpool_cpi::cpi::increase_liquidity_v2(
+            cpi_ctx_increase_liquidity,
+            liquidity,
+            token_max_a,
+            token_max_b,
+            None,
+        )
+    }
+
+    /// Removes `liquidity` from `position` into the vault's token accounts.
+    pub fn decrease_liquidity(
+        &self,
+        position: &PositionAccounts<'info>,
+        signer_seeds: &[&[&[u8]]],
+        liquidity: u128,
+        token_min_a: u64,
+        token_min_b: u64,
+    ) -> Result<()> {
+        let cpi_ctx_decrease_liquidity = CpiContext::new_with_signer(
+            self.whirlpool_program.clone(),
+            self.modify_liquidity_accounts(position),
+            signer_seeds,
+        );
+
+        // execute CPI
+        msg!("CPI: whirlpool decrease_liquidity_v2 instruction");
+        whirlpool_cpi::cpi::decrease_liquidity_v2(
+            cpi_ctx_decrease_liquidity,
+            liquidity,
+            token_min_a,
+            token_min_b,
+            None,
+        )
+    }
+}
+
+/// Accounts to open a position owned by the vault.
+pub struct OpenPositionAccounts<'info> {
+    pub whirlpool_program: AccountInfo<'info>,
+    pub funder: AccountInfo<'info>,
+    pub position: AccountInfo<'info>,
+    pub position_mint: AccountInfo<'info>,
+    pub position_token_account: AccountInfo<'info>,
+    pub whirlpool: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub rent: AccountInfo<'info>,
+    pub associated_token_program: AccountInfo<'info>,
+    /// `WithMetadata` only
+    pub position_metadata_account: Option<AccountInfo<'info>>,
+    /// `WithMetadata` only
+    pub metadata_program: Option<AccountInfo<'info>>,
+    /// `WithMetadata` and `TokenExtensions`
+    pub metadata_update_auth: Option<AccountInfo<'info>>,
+    /// `TokenExtensions` only
+    pub token_2022_program: Option<AccountInfo<'info>>,
+}
+
+/// Opens a `[tick_lower_index, tick_upper_index)` position of `vault.position_kind`, owned and
+/// signed for by the vault.
+pub fn open_position<'info>(
+    vault: &Account<'info, Vault>,
+    accounts: OpenPositionAccounts<'info>,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+) -> Result<()> {
+    let vault_seeds = vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+
+    match vault.position_kind {
+        PositionKind::Plain => {
+            let cpi_accounts = whirlpool_cpi::cpi::accounts::OpenPosition {
+                funder: accounts.funder,
+                owner: vault.to_account_info(),
+                position: accounts.position,
+                position_mint: accounts.position_mint,
+                position_token_account: accounts.position_token_account,
+                whirlpool: accounts.whirlpool,
+                token_program: accounts.token_program,
+                system_program: accounts.system_program,
+                rent: accounts.rent,
+                associated_token_program: accounts.associated_token_program,
+            };
+            let cpi_ctx =
+                CpiContext::new_with_signer(accounts.whirlpool_program, cpi_accounts, signer_seeds);
+
+            // execute CPI
+            msg!("CPI: whirlpool open_position instruction");
+            whirlpool_cpi::cpi::open_position(
+                cpi_ctx,
+                whirlpool_cpi::state::OpenPositionBumps { position_bump: 0 }, // passed bump is no longer used
+                tick_lower_index,
+                tick_upper_index,
+            )
+        }
+        PositionKind::WithMetadata => {
+            let (
+                Some(position_metadata_account),
+                Some(metadata_program),
+                Some(metadata_update_auth),
+            ) = (
+                accounts.position_metadata_account,
+                accounts.metadata_program,
+                accounts.metadata_update_auth,
+            )
+            else {
+                return err!(CustomError::MissingPositionAccounts);
+            };
+            let cpi_accounts = whirlpool_cpi::cpi::accounts::OpenPositionWithMetadata {
+                funder: accounts.funder,
+                owner: vault.to_account_info(),
+                position: accounts.position,
+                position_mint: accounts.position_mint,
+                position_metadata_account,
+                position_token_account: accounts.position_token_account,
+                whirlpool: accounts.whirlpool,
+                token_program: accounts.token_program,
+                system_program: accounts.system_program,
+                rent: accounts.rent,
+                associated_token_program: accounts.associated_token_program,
+                metadata_program,
+                metadata_update_auth,
+            };
+            let cpi_ctx =
+                CpiContext::new_with_signer(accounts.whirlpool_program, cpi_accounts, signer_seeds);
+
+            // execute CPI
+            msg!("CPI: whirlpool open_position_with_metadata instruction");
+            whirlpool_cpi::cpi::open_position_with_metadata(
+                cpi_ctx,
+                // passed bumps are no longer used
+                whirlpool_cpi::state::OpenPositionWithMetadataBumps {
+                    position_bump: 0,
+                    metadata_bump: 0,
+                },
+                tick_lower_index,
+                tick_upper_index,
+            )
+        }
+        PositionKind::TokenExtensions => {
+            let (Some(token_2022_program), Some(metadata_update_auth)) =
+                (accounts.token_2022_program, accounts.metadata_update_auth)
+            else {
+                return err!(CustomError::MissingPositionAccounts);
+            };
+            let cpi_accounts = whirlpool_cpi::cpi::accounts::OpenPositionWithTokenExtensions {
+                funder: accounts.funder,
+                owner: vault.to_account_info(),
+                position: accounts.position,
+                position_mint: accounts.position_mint,
+                position_token_account: accounts.position_token_account,
+                whirlpool: accounts.whirlpool,
+                token2022_program: token_2022_program,
+                system_program: accounts.system_program,
+                associated_token_program: accounts.associated_token_program,
+                metadata_update_auth,
+            };
+            let cpi_ctx =
+                CpiContext::new_with_signer(accounts.whirlpool_program, cpi_accounts, signer_seeds);
+
+            // execute CPI
+            msg!("CPI: whirlpool open_position_with_token_extensions instruction");
+            whirlpool_cpi::cpi::open_position_with_token_extensions(
+                cpi_ctx,
+                tick_lower_index,
+                tick_upper_index,
+                true,
+            )
+        }
+    }
+}
+
+/// Accounts to close a position owned by the vault.
+pub struct ClosePositionAccounts<'info> {
+    pub whirlpool_program: AccountInfo<'info>,
+    /// Receives the rent of the closed position
+    pub receiver: AccountInfo<'info>,
+    pub position: AccountInfo<'info>,
+    pub position_mint: AccountInfo<'info>,
+    pub position_token_account: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+    /// `TokenExtensions` only
+    pub token_2022_program: Option<AccountInfo<'info>>,
+}
+
+/// Closes a position opened by [`open_position`], with the instruction matching
+/// `vault.position_kind`. The position must already be empty.
+pub fn close_position<'info>(
+    vault: &Account<'info, Vault>,
+    accounts: ClosePositionAccounts<'info>,
+) -> Result<()> {
+    let vault_seeds = vault.signer_seeds();
+    let signer_seeds = &[&vault_seeds[..]];
+
+    match vault.position_kind {
+        // metadata positions are still plain SPL Token NFTs
+        PositionKind::Plain | PositionKind::WithMetadata => {
+            let cpi_accounts = whirlpool_cpi::cpi::accounts::ClosePosition {
+                position_authority: vault.to_account_info(),
+                receiver: accounts.receiver,
+                position: accounts.position,
+                position_mint: accounts.position_mint,
+                position_token_account: accounts.position_token_account,
+                token_program: accounts.token_program,
+            };
+            let cpi_ctx =
+                CpiContext::new_with_signer(accounts.whirlpool_program, cpi_accounts, signer_seeds);
+
+            // execute CPI
+            msg!("CPI: whirlpool close_position instruction");
+            whirlpool_cpi::cpi::close_position(cpi_ctx)
+        }
+        PositionKind::TokenExtensions => {
+            let token_2022_program = accounts
+                .token_2022_program
+                .ok_or_else(|| error!(CustomError::MissingPositionAccounts))?;
+            let cpi_accounts = whirlpool_cpi::cpi::accounts::ClosePositionWithTokenExtensions {
+                position_authority: vault.to_account_info(),
+                receiver: accounts.receiver,
+                position: accounts.position,
+                position_mint: accounts.position_mint,
+                position_token_account: accounts.position_token_account,
+                token2022_program: token_2022_program,
+            };
+            let cpi_ctx =
+                CpiContext::new_with_signer(accounts.whirlpool_program, cpi_accounts, signer_seeds);
+
+            // execute CPI
+            msg!("CPI: whirlpool close_position_with_token_extensions instruction");
+            whirlpool_cpi::cpi::close_position_with_token_extensions(cpi_ctx)
+        }
+    }
+}
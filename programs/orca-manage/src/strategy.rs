@@ -0,0 +1,315 @@
+use anchor_lang::prelude::*;
+use whirlpool_cpi::state::Whirlpool;
+
+use crate::liquidity_vault::CustomError;
+use crate::math::{full_math, price_math::Price, tick_math};
+use crate::BPS_DENOMINATOR;
+
+/// How `rebalance` places the vault's range around the pool's current price.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum Strategy {
+    /// `tick_width` ticks centred on the current tick
+    FixedWidth { tick_width: u32 },
+    /// From `band_bps` below to `band_bps` above the current price
+    PercentageBand { band_bps: u16 },
+    /// `ticks_below` under and `ticks_above` over the current tick
+    AsymmetricSkew { ticks_below: u32, ticks_above: u32 },
+    /// Every initializable tick of the pool
+    FullRange,
+}
+
+impl Strategy {
+    pub fn validate(&self) -> Result<()> {
+        let is_valid = match *self {
+            Strategy::FixedWidth { tick_width } => tick_width > 0,
+            Strategy::PercentageBand { band_bps } => band_bps > 0 && band_bps < BPS_DENOMINATOR,
+            Strategy::AsymmetricSkew {
+                ticks_below,
+                ticks_above,
+            } => ticks_below > 0 || ticks_above > 0,
+            Strategy::FullRange => true,
+        };
+        require!(is_valid, CustomError::InvalidStrategy);
+        Ok(())
+    }
+
+    /// Initializable `(tick_lower_index, tick_upper_index)` for the pool's current state. The
+    /// lower tick is rounded down and the upper one up, so the range never ends up narrower.
+    pub fn get_tick_range(&self, whirlpool: &Whirlpool) -> Result<(i32, i32)> {
+        self.get_tick_range_at(
+            whirlpool.tick_current_index,
+            whirlpool.sqrt_price,
+            whirlpool.tick_spacing,
+        )
+    }
+
+    /// [`Strategy::get_tick_range`] for a pool at `tick_current_index`/`sqrt_price` with
+    /// `tick_spacing`.
+    fn get_tick_range_at(
+        &self,
+        tick_current_index: i32,
+        sqrt_price: u128,
+        tick_spacing: u16,
+    ) -> Result<(i32, i32)> {
+        let tick_current = tick_current_index as i64;
+        let (tick_lower, tick_upper) = match *self {
+            Strategy::FixedWidth { tick_width } => {
+                let tick_lower = tick_current - tick_width as i64 / 2;
+                (tick_lower, tick_lower + tick_width as i64)
+            }
+            Strategy::PercentageBand { band_bps } => {
+                // Ratios of prices do not depend on the mints' decimals, work in raw units
+                let price = Price::from_sqrt_price_x64(sqrt_price, 0, 0)?.to_x64();
+                let price_lower = full_math::mul_div_floor(
+                    price,
+                    (BPS_DENOMINATOR - band_bps) as u128,
+                    BPS_DENOMINATOR as u128,
+                )?;
+                let price_upper = full_math::mul_div_ceil(
+                    price,
+                    (BPS_DENOMINATOR + band_bps) as u128,
+                    BPS_DENOMINATOR as u128,
+                )?;
+                let tick_lower =
+                    tick_math::tick_index_from_price(Price::from_x64(price_lower), 0, 0)?;
+                // tick_index_from_price rounds down, step past the upper price
+                let tick_upper =
+                    tick_math::tick_index_from_price(Price::from_x64(price_upper), 0, 0)?;
+                (tick_lower as i64, tick_upper as i64 + 1)
+            }
+            Strategy::AsymmetricSkew {
+                ticks_below,
+                ticks_above,
+            } => (
+                tick_current - ticks_below as i64,
+                tick_current + ticks_above as i64,
+            ),
+            Strategy::FullRange => (
+                tick_math::MIN_TICK_INDEX as i64,
+                tick_math::MAX_TICK_INDEX as i64,
+            ),
+        };
+
+        let clamp = |tick_index: i64| {
+            tick_index.clamp(
+                tick_math::MIN_TICK_INDEX as i64,
+                tick_math::MAX_TICK_INDEX as i64,
+            ) as i32
+        };
+        let tick_lower_index =
+            tick_math::get_initializable_tick_index(clamp(tick_lower), tick_spacing, false);
+        let tick_upper_index =
+            tick_math::get_initializable_tick_index(clamp(tick_upper), tick_spacing, true);
+
+        // Both ends may land in the same tick spacing, the position still needs a non-empty range
+        if tick_upper_index > tick_lower_index {
+            Ok((tick_lower_index, tick_upper_index))
+        } else {
+            let tick_upper_index = tick_lower_index + tick_spacing as i32;
+            require!(
+                tick_upper_index <= tick_math::MAX_TICK_INDEX,
+                CustomError::InvalidTickIndex
+            );
+            Ok((tick_lower_index, tick_upper_index))
+        }
+    }
+}
+
+/// When `rebalance` is allowed to move the vault's range.
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace,
+)]
+pub struct RebalanceTrigger {
+    /// Rebalance once the current tick is this close to either end of the range, 0 waits until
+    /// the price leaves the range
+    pub edge_distance_ticks: u32,
+    /// Seconds that must pass between two rebalances
+    pub min_rebalance_interval: i64,
+}
+
+impl RebalanceTrigger {
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.min_rebalance_interval >= 0,
+            CustomError::InvalidRebalanceTrigger
+        );
+        Ok(())
+    }
+
+    /// Whether the current tick is out of `[tick_lower_index, tick_upper_index)` or within
+    /// `edge_distance_ticks` of its ends.
+    pub fn is_triggered(
+        &self,
+        tick_current_index: i32,
+        tick_lower_index: i32,
+        tick_upper_index: i32,
+    ) -> bool {
+        let tick_current_index = tick_current_index as i64;
+        let edge_distance_ticks = self.edge_distance_ticks as i64;
+        tick_current_index < tick_lower_index as i64 + edge_distance_ticks
+            || tick_current_index >= tick_upper_index as i64 - edge_distance_ticks
+    }
+
+    /// Whether enough time has passed since `last_rebalance_ts`.
+    pub fn is_cooled_down(&self, last_rebalance_ts: i64, now: i64) -> bool {
+        now.saturating_sub(last_rebalance_ts) >= self.min_rebalance_interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::price_math::Q64;
+    use crate::math::tick_math::{MAX_SQRT_PRICE_X64, MAX_TICK_INDEX, MIN_TICK_INDEX};
+
+    fn tick_range(strategy: Strategy, tick_current_index: i32, tick_spacing: u16) -> (i32, i32) {
+        let sqrt_price = tick_math::sqrt_price_from_tick_index(tick_current_index).unwrap();
+        strategy
+            .get_tick_range_at(tick_current_index, sqrt_price, tick_spacing)
+            .unwrap()
+    }
+
+    #[test]
+    fn validate_rejects_empty_ranges() {
+        assert!(Strategy::FixedWidth { tick_width: 0 }.validate().is_err());
+        assert!(Strategy::FixedWidth { tick_width: 1 }.validate().is_ok());
+        assert!(Strategy::PercentageBand { band_bps: 0 }.validate().is_err());
+        assert!(Strategy::PercentageBand {
+            band_bps: BPS_DENOMINATOR
+        }
+        .validate()
+        .is_err());
+        assert!(Strategy::PercentageBand { band_bps: 1 }.validate().is_ok());
+        assert!(Strategy::AsymmetricSkew {
+            ticks_below: 0,
+            ticks_above: 0
+        }
+        .validate()
+        .is_err());
+        assert!(Strategy::AsymmetricSkew {
+            ticks_below: 0,
+            ticks_above: 1
+        }
+        .validate()
+        .is_ok());
+        assert!(Strategy::FullRange.validate().is_ok());
+    }
+
+    #[test]
+    fn fixed_width_centres_on_the_current_tick() {
+        let strategy = Strategy::FixedWidth { tick_width: 100 };
+        assert_eq!(tick_range(strategy, 0, 1), (-50, 50));
+        // rounded outwards to the tick spacing
+        assert_eq!(tick_range(strategy, 0, 64), (-64, 64));
+    }
+
+    #[test]
+    fn asymmetric_skew_offsets_each_side() {
+        let strategy = Strategy::AsymmetricSkew {
+            ticks_below: 10,
+            ticks_above: 30,
+        };
+        assert_eq!(tick_range(strategy, 100, 1), (90, 130));
+        assert_eq!(tick_range(strategy, -100, 8), (-112, -64));
+    }
+
+    #[test]
+    fn full_range_covers_every_initializable_tick() {
+        assert_eq!(tick_range(Strategy::FullRange, 0, 64), (-443584, 443584));
+        assert_eq!(
+            tick_range(Strategy::FullRange, 0, 1),
+            (MIN_TICK_INDEX, MAX_TICK_INDEX)
+        );
+    }
+
+    #[test]
+    fn percentage_band_rounds_outwards() {
+        // 0.99 and 1.01 fall inside ticks -101 and 99, the upper end steps past 1.01
+        let strategy = Strategy::PercentageBand { band_bps: 100 };
+        assert_eq!(tick_range(strategy, 0, 1), (-101, 100));
+        assert_eq!(tick_range(strategy, 0, 64), (-128, 128));
+
+        let (tick_lower_index, tick_upper_index) = tick_range(strategy, 0, 1);
+        let price = |tick_index| {
+            Price::from_sqrt_price_x64(
+                tick_math::sqrt_price_from_tick_index(tick_index).unwrap(),
+                0,
+                0,
+            )
+            .unwrap()
+            .to_x64()
+        };
+        assert!(price(tick_lower_index) <= Q64 * 99 / 100);
+        assert!(price(tick_upper_index) >= Q64 * 101 / 100);
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_tick_bounds() {
+        let strategy = Strategy::FixedWidth { tick_width: 1_000 };
+        assert_eq!(
+            tick_range(strategy, MAX_TICK_INDEX - 10, 1),
+            (MAX_TICK_INDEX - 510, MAX_TICK_INDEX)
+        );
+        assert_eq!(
+            tick_range(strategy, MIN_TICK_INDEX + 10, 1),
+            (MIN_TICK_INDEX, MIN_TICK_INDEX + 510)
+        );
+    }
+
+    #[test]
+    fn collapsed_ranges_widen_to_one_tick_spacing() {
+        // both ends clamp into the lowest initializable tick
+        let strategy = Strategy::FixedWidth { tick_width: 10 };
+        assert_eq!(tick_range(strategy, MIN_TICK_INDEX, 64), (-443584, -443520));
+        // and there is no room above the highest one
+        let sqrt_price = tick_math::sqrt_price_from_tick_index(MAX_TICK_INDEX).unwrap();
+        assert!(strategy
+            .get_tick_range_at(MAX_TICK_INDEX, sqrt_price, 64)
+            .is_err());
+    }
+
+    #[test]
+    fn percentage_band_overflow_is_an_error() {
+        let strategy = Strategy::PercentageBand { band_bps: 100 };
+        assert!(strategy
+            .get_tick_range_at(MAX_TICK_INDEX, MAX_SQRT_PRICE_X64, 1)
+            .is_err());
+    }
+
+    #[test]
+    fn zero_edge_distance_triggers_only_outside_the_range() {
+        let trigger = RebalanceTrigger {
+            edge_distance_ticks: 0,
+            min_rebalance_interval: 0,
+        };
+        assert!(trigger.is_triggered(-101, -100, 100));
+        assert!(!trigger.is_triggered(-100, -100, 100));
+        assert!(!trigger.is_triggered(99, -100, 100));
+        // the upper tick is outside the range
+        assert!(trigger.is_triggered(100, -100, 100));
+    }
+
+    #[test]
+    fn edge_distance_triggers_near_either_end() {
+        let trigger = RebalanceTrigger {
+            edge_distance_ticks: 10,
+            min_rebalance_interval: 0,
+        };
+        assert!(trigger.is_triggered(-91, -100, 100));
+        assert!(!trigger.is_triggered(-90, -100, 100));
+        assert!(!trigger.is_triggered(89, -100, 100));
+        assert!(trigger.is_triggered(90, -100, 100));
+    }
+
+    #[test]
+    fn cooldown_ends_at_exactly_the_interval() {
+        let trigger = RebalanceTrigger {
+            edge_distance_ticks: 0,
+            min_rebalance_interval: 3_600,
+        };
+        assert!(!trigger.is_cooled_down(1_000, 4_599));
+        assert!(trigger.is_cooled_down(1_000, 4_600));
+        // a vault that never rebalanced is cooled down
+        assert!(trigger.is_cooled_down(0, 3_600));
+    }
+}
@@ -0,0 +1,41 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+};
+
+use crate::liquidity_vault::CustomError;
+
+/// Transfer fee config of a Token-2022 mint, `None` for SPL Token mints and mints without one.
+fn get_transfer_fee_config(mint: &AccountInfo) -> Result<Option<TransferFeeConfig>> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(None);
+    }
+    let mint_data = mint.try_borrow_data()?;
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    Ok(mint.get_extension::<TransferFeeConfig>().ok().copied())
+}
+
+/// Amount that arrives when `amount` of `mint` is transferred in the current epoch.
+pub fn get_amount_after_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    let Some(config) = get_transfer_fee_config(mint)? else {
+        return Ok(amount);
+    };
+    let epoch = Clock::get()?.epoch;
+    config
+        .get_epoch_fee(epoch)
+        .calculate_post_fee_amount(amount)
+        .ok_or_else(|| error!(CustomError::MathOverflow))
+}
+
+/// Amount of `mint` to transfer in the current epoch so that `amount` arrives.
+pub fn get_amount_before_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    let Some(config) = get_transfer_fee_config(mint)? else {
+        return Ok(amount);
+    };
+    let epoch = Clock::get()?.epoch;
+    config
+        .get_epoch_fee(epoch)
+        .calculate_pre_fee_amount(amount)
+        .ok_or_else(|| error!(CustomError::MathOverflow))
+}